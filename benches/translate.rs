@@ -0,0 +1,88 @@
+//! Throughput of translation with the built-in dictionary, and with the same
+//! dictionary extended by a few thousand extra rules.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use yeold::autorp::AutoRP;
+
+/// A few lines of typical chat, repeated to make a log of about `bytes`.
+fn chat_log(bytes: usize) -> String {
+    const LINES: &[&str] = &[
+        "Hello there! How are you doing today, my friend?",
+        "I don't know, what are you doing later? Maybe we can go to the shop.",
+        "Thank you very much, that is really kind of you.",
+        "He runs to the tavern and she tries to keep up with him.",
+        "Your horse is in the stable. You should feed it before you leave.",
+        "lol that was a great joke, tell me another one",
+    ];
+
+    let mut log = String::with_capacity(bytes + 100);
+    for line in LINES.iter().cycle() {
+        if log.len() >= bytes {
+            break;
+        }
+        log.push_str(line);
+        log.push('\n');
+    }
+    log
+}
+
+/// The built-in dictionary with `count` extra single word, previous word and
+/// phrase rules, as a house-style extension might add.
+fn extended(count: usize) -> AutoRP {
+    let mut rules = String::from("\"autorp.txt\" { \"word_replacements\" {\n");
+    for i in 0..count {
+        let rule = match i % 3 {
+            0 => format!("\"word\" \"word{i}\" \"replacement\" \"replaced{i}\""),
+            1 => format!("\"prev\" \"the\" \"word\" \"word{i}\" \"replacement\" \"ye {i}\""),
+            _ => format!("\"phrase\" \"one two word{i}\" \"replacement\" \"phrase{i}\""),
+        };
+        writeln!(rules, "\"1\" {{ {rule} }}").unwrap();
+    }
+    rules.push_str("} }");
+
+    AutoRP::builder()
+        .rules(AutoRP::from_str(&rules).unwrap())
+        .build()
+}
+
+fn translate(c: &mut Criterion) {
+    let dictionaries = [
+        ("default", AutoRP::default()),
+        ("extended", extended(3_000)),
+    ];
+
+    let mut group = c.benchmark_group("translate");
+    for size in [1_000, 100_000] {
+        let log = chat_log(size);
+        group.throughput(Throughput::Bytes(log.len() as u64));
+        for (name, autorp) in &dictionaries {
+            group.bench_with_input(BenchmarkId::new(*name, size), &log, |b, log| {
+                let mut rng = StdRng::seed_from_u64(0);
+                b.iter(|| autorp.translate_with_rng(log, &mut rng));
+            });
+        }
+    }
+    group.finish();
+
+    let log = chat_log(100_000);
+    let autorp = AutoRP::default();
+    let mut group = c.benchmark_group("translate_postprocess");
+    group.throughput(Throughput::Bytes(log.len() as u64));
+    group.bench_function("default", |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        b.iter(|| autorp.translate_postprocess_with_rng(&log, true, true, &mut rng));
+    });
+    group.finish();
+}
+
+fn load(c: &mut Criterion) {
+    c.bench_function("load_default", |b| b.iter(AutoRP::default));
+}
+
+criterion_group!(benches, translate, load);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+//! Lists every way the rules could translate each part of the input, so that
+//! a replacement can be picked by hand rather than at random.
+
+use std::ops::Range;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::autorp::MatchKind;
+
+/// A rule which matches a part of the input, and everything it could
+/// replace it with.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    /// The index of the rule in the list of rules
+    pub rule: usize,
+    /// How the rule matches
+    #[wasm_bindgen(skip)]
+    pub kind: MatchKind,
+    /// The bytes of the input the rule would replace, which may start before
+    /// the word it is listed for when it matches several words
+    #[wasm_bindgen(skip)]
+    pub source: Range<usize>,
+    /// `source` in UTF-16 code units, which is how JavaScript indexes strings
+    #[wasm_bindgen(skip)]
+    pub source_utf16: Range<usize>,
+    /// The probability that the rule is applied when it matches
+    pub probability: f64,
+    /// The replacements the rule chooses from, as written in the rules, so
+    /// templates such as `&god` are not expanded
+    pub replacements: Vec<String>,
+    /// The words the rule may put before its replacement
+    pub prepend: Vec<String>,
+    /// How many of the words in `prepend` are put before the replacement
+    pub prepend_count: u32,
+}
+
+/// The getters give offsets in UTF-16 code units, for slicing JavaScript
+/// strings with.
+#[wasm_bindgen]
+impl Alternative {
+    #[wasm_bindgen(getter)]
+    pub fn source_start(&self) -> usize {
+        self.source_utf16.start
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn source_end(&self) -> usize {
+        self.source_utf16.end
+    }
+
+    /// How the rule matches, e.g. `word` or `2 word phrase`.
+    #[wasm_bindgen(getter, js_name = kind)]
+    pub fn kind_name(&self) -> String {
+        self.kind.to_string()
+    }
+}
+
+/// The rules matching a word or punctuation mark of the input, as returned
+/// by `AutoRP::alternatives`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternatives {
+    /// The bytes of the word or punctuation mark
+    #[wasm_bindgen(skip)]
+    pub source: Range<usize>,
+    /// `source` in UTF-16 code units
+    #[wasm_bindgen(skip)]
+    pub source_utf16: Range<usize>,
+    /// The rules matching it, along with the words before it, in the order
+    /// they are tried when translating
+    pub rules: Vec<Alternative>,
+}
+
+#[wasm_bindgen]
+impl Alternatives {
+    /// In UTF-16 code units, like `Alternative::source_start`.
+    #[wasm_bindgen(getter)]
+    pub fn source_start(&self) -> usize {
+        self.source_utf16.start
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn source_end(&self) -> usize {
+        self.source_utf16.end
+    }
+}
@@ -1,11 +1,10 @@
 //! This module parses the input into tokens that can then be used for translation
 
 use nom::branch::alt;
-use nom::bytes::complete::take_while;
-use nom::character::complete::*;
-use nom::combinator::map;
-use nom::error::ErrorKind;
-use nom::multi::many0;
+use nom::bytes::complete::{take_while1, take_while_m_n};
+use nom::combinator::{map, recognize};
+use nom::multi::{many0, many0_count};
+use nom::sequence::pair;
 /// This module provides functionality for tokenizing ARP (Address Resolution Protocol) data.
 /// It uses the `nom` crate for parsing sequences of bytes.
 ///
@@ -15,26 +14,68 @@ use nom::IResult;
 
 #[derive(Debug, PartialEq)]
 pub enum ArpToken<'a> {
+    /// A run of whitespace
     Ws(&'a str),
+    /// A bare word, possibly containing inner apostrophes or hyphens (`don't`, `ill-bred`)
     Word(&'a str),
+    /// A run of anything that is neither whitespace nor part of a word
+    Punct(&'a str),
 }
 
-fn parse_ws(input: &str) -> IResult<&str, ArpToken> {
-    map(multispace1, ArpToken::Ws)(input)
+impl<'a> ArpToken<'a> {
+    /// The text of the token, exactly as it appears in the input.
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            ArpToken::Ws(s) | ArpToken::Word(s) | ArpToken::Punct(s) => s,
+        }
+    }
 }
 
-fn parse_word(input: &str) -> IResult<&str, ArpToken> {
-    if input.is_empty() {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            "Nothing to consume",
-            ErrorKind::Eof,
-        )));
-    }
-    map(take_while(|c: char| !c.is_whitespace()), ArpToken::Word)(input)
+/// Returns `true` for characters which may appear between two word characters
+/// without splitting the word.
+pub fn is_joiner(c: char) -> bool {
+    matches!(c, '\'' | '’' | '-')
+}
+
+fn parse_ws(input: &str) -> IResult<&str, ArpToken<'_>> {
+    map(take_while1(char::is_whitespace), ArpToken::Ws)(input)
+}
+
+fn parse_word(input: &str) -> IResult<&str, ArpToken<'_>> {
+    let alnum1 = |i| take_while1(|c: char| c.is_alphanumeric())(i);
+    map(
+        recognize(pair(
+            alnum1,
+            many0_count(pair(take_while_m_n(1, 1, is_joiner), alnum1)),
+        )),
+        ArpToken::Word,
+    )(input)
+}
+
+fn parse_punct(input: &str) -> IResult<&str, ArpToken<'_>> {
+    map(
+        take_while1(|c: char| !c.is_whitespace() && !c.is_alphanumeric()),
+        ArpToken::Punct,
+    )(input)
+}
+
+pub fn parse(input: &str) -> IResult<&str, Vec<ArpToken<'_>>> {
+    many0(alt((parse_ws, parse_word, parse_punct)))(input)
 }
 
-pub fn parse(input: &str) -> IResult<&str, Vec<ArpToken>> {
-    many0(alt((parse_ws, parse_word)))(input)
+/// Splits all of `input` into tokens. Every character is whitespace, part of
+/// a word or punctuation, so nothing should be left over, but anything that
+/// is becomes a final `Punct` token rather than being dropped.
+pub fn tokenize(input: &str) -> Vec<ArpToken<'_>> {
+    match parse(input) {
+        Ok(("", tokens)) => tokens,
+        Ok((rest, mut tokens)) => {
+            debug_assert!(false, "unparsed input {:?}", rest);
+            tokens.push(ArpToken::Punct(rest));
+            tokens
+        }
+        Err(_) => vec![ArpToken::Punct(input)],
+    }
 }
 
 #[cfg(test)]
@@ -46,6 +87,9 @@ mod tests {
         assert_eq!(parse_ws("   "), Ok(("", ArpToken::Ws("   "))));
         assert_eq!(parse_ws("\t"), Ok(("", ArpToken::Ws("\t"))));
         assert_eq!(parse_ws("\n"), Ok(("", ArpToken::Ws("\n"))));
+        assert_eq!(parse_ws("\u{a0}x"), Ok(("x", ArpToken::Ws("\u{a0}"))));
+        assert_eq!(parse_ws("\u{2003}"), Ok(("", ArpToken::Ws("\u{2003}"))));
+        assert_eq!(parse_ws("\x0b"), Ok(("", ArpToken::Ws("\x0b"))));
     }
 
     #[test]
@@ -53,6 +97,19 @@ mod tests {
         assert_eq!(parse_word("hello"), Ok(("", ArpToken::Word("hello"))));
         assert_eq!(parse_word("world "), Ok((" ", ArpToken::Word("world"))));
         assert_eq!(parse_word("rust"), Ok(("", ArpToken::Word("rust"))));
+        assert_eq!(parse_word("you!"), Ok(("!", ArpToken::Word("you"))));
+        assert_eq!(parse_word("don't"), Ok(("", ArpToken::Word("don't"))));
+        assert_eq!(parse_word("ill-bred"), Ok(("", ArpToken::Word("ill-bred"))));
+        assert_eq!(parse_word("goin'"), Ok(("'", ArpToken::Word("goin"))));
+        assert!(parse_word("!").is_err());
+    }
+
+    #[test]
+    fn test_parse_punct() {
+        assert_eq!(parse_punct("!"), Ok(("", ArpToken::Punct("!"))));
+        assert_eq!(parse_punct("?! x"), Ok((" x", ArpToken::Punct("?!"))));
+        assert_eq!(parse_punct("(friend"), Ok(("friend", ArpToken::Punct("("))));
+        assert!(parse_punct("word").is_err());
     }
 
     #[test]
@@ -79,5 +136,38 @@ mod tests {
                 ]
             ))
         );
+        assert_eq!(
+            parse("thank you! (friend)"),
+            Ok((
+                "",
+                vec![
+                    ArpToken::Word("thank"),
+                    ArpToken::Ws(" "),
+                    ArpToken::Word("you"),
+                    ArpToken::Punct("!"),
+                    ArpToken::Ws(" "),
+                    ArpToken::Punct("("),
+                    ArpToken::Word("friend"),
+                    ArpToken::Punct(")"),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn test_tokenize_unicode_whitespace() {
+        assert_eq!(
+            tokenize("hello\u{a0}world friend"),
+            vec![
+                ArpToken::Word("hello"),
+                ArpToken::Ws("\u{a0}"),
+                ArpToken::Word("world"),
+                ArpToken::Ws(" "),
+                ArpToken::Word("friend"),
+            ]
+        );
+        let text = "one\x0btwo\u{2003}three \u{3000}four";
+        let joined: String = tokenize(text).iter().map(ArpToken::as_str).collect();
+        assert_eq!(joined, text);
     }
 }
@@ -0,0 +1,251 @@
+//! Fixes the indefinite article in front of words whose first sound changed
+//! during translation, e.g. "a idiot" after an insult was prepended.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::arp_tokenizer::{self, ArpToken};
+use crate::casing::Casing;
+use crate::explain::{apply_edits, Edit, Origin};
+
+/// Beginnings of words spelt with a vowel but spoken starting with a
+/// consonant sound, which take "a".
+const CONSONANT_SOUNDING: &[&str] = &[
+    "eu", "ewe", "ubiq", "unic", "unif", "unio", "uniq", "unis", "unit", "univ", "ura", "ure",
+    "uri", "uro", "usa", "use", "usu", "ute", "uti", "utop",
+];
+
+/// Beginnings of words with a silent "h", which take "an".
+const SILENT_H: &[&str] = &["heir", "herb", "honest", "honor", "honour", "hour"];
+
+/// Letters whose names start with a vowel sound, such as "ef" and "em".
+const VOWEL_NAMED: &[char] = &['a', 'e', 'f', 'h', 'i', 'l', 'm', 'n', 'o', 'r', 's', 'x'];
+
+/// Returns `true` if `word` is spelt out letter by letter, like "FBI" or
+/// "MP3", rather than read as a word, like "NASA". In text written in
+/// capitals, only words with digits or without vowels are.
+fn is_initialism(word: &str, in_capitals: bool) -> bool {
+    if word.chars().count() < 2 || word.chars().any(char::is_lowercase) {
+        return false;
+    }
+    let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+    let vowels = word.chars().filter(|c| "AEIOU".contains(*c)).count();
+    word.chars().any(|c| c.is_ascii_digit()) || vowels == 0 || (letters <= 3 && !in_capitals)
+}
+
+/// Returns `true` if the number starting `word` is spoken starting with a
+/// vowel sound: eight, eleven or eighteen, possibly followed by thousand,
+/// million and so on.
+fn number_wants_an(word: &str) -> bool {
+    let digits: String = word
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',')
+        .filter(char::is_ascii_digit)
+        .collect();
+    digits.starts_with('8')
+        || ((digits.starts_with("11") || digits.starts_with("18")) && digits.len() % 3 == 2)
+}
+
+/// Returns `true` if `word` is spoken starting with a vowel sound, and so
+/// takes "an" rather than "a".
+pub(crate) fn wants_an(word: &str) -> bool {
+    sounds_like_vowel(word, false)
+}
+
+/// Like `wants_an`, for a word in text which may be written in capitals.
+fn sounds_like_vowel(word: &str, in_capitals: bool) -> bool {
+    if word.starts_with(|c: char| c.is_ascii_digit()) {
+        return number_wants_an(word);
+    }
+    if is_initialism(word, in_capitals) {
+        let first = word.chars().next().map(|c| c.to_ascii_lowercase());
+        return first.is_some_and(|c| VOWEL_NAMED.contains(&c));
+    }
+
+    let lower = word.to_lowercase();
+    if SILENT_H.iter().any(|p| lower.starts_with(p)) {
+        return true;
+    }
+    if matches!(lower.as_str(), "one" | "once")
+        || CONSONANT_SOUNDING.iter().any(|p| lower.starts_with(p))
+    {
+        return false;
+    }
+    lower.starts_with(['a', 'e', 'i', 'o', 'u'])
+}
+
+/// Makes every "a" or "an" directly followed by a word agree with it,
+/// keeping the article's casing. Returns the text borrowed if nothing changed.
+pub fn agree_articles(text: &str) -> Cow<'_, str> {
+    let all = 0..text.len();
+    match article_fixes(text, std::slice::from_ref(&all)).as_slice() {
+        [] => Cow::Borrowed(text),
+        fixes => Cow::Owned(apply_edits(text, fixes)),
+    }
+}
+
+/// Returns `true` if the capital "A" at `tokens[i]` is the name of the
+/// letter, as in "Vitamin A" or "plan A", rather than an article. Only an
+/// "A" starting a sentence, or in text written in capitals, is an article.
+fn is_letter(tokens: &[ArpToken], i: usize) -> bool {
+    if tokens[i].as_str() != "A" {
+        return false;
+    }
+    match previous(tokens, i) {
+        Some(ArpToken::Word(prev)) => prev.chars().any(char::is_lowercase),
+        Some(ArpToken::Punct(punct)) => !punct.ends_with(['.', '!', '?', '"', '“']),
+        _ => false,
+    }
+}
+
+/// The token before `tokens[i]` on the same line, skipping whitespace.
+fn previous<'t, 'a>(tokens: &'t [ArpToken<'a>], i: usize) -> Option<&'t ArpToken<'a>> {
+    tokens[..i]
+        .iter()
+        .rev()
+        .take_while(|t| !matches!(t, ArpToken::Ws(ws) if ws.contains('\n')))
+        .find(|t| !matches!(t, ArpToken::Ws(_)))
+}
+
+/// Returns `true` if the article at `tokens[i]` is in text written in
+/// capitals, as "AN" or as an "A" after a word in capitals.
+fn in_capitals(tokens: &[ArpToken], i: usize) -> bool {
+    match tokens[i].as_str() {
+        "AN" => true,
+        "A" => matches!(previous(tokens, i), Some(ArpToken::Word(prev))
+            if !prev.chars().any(char::is_lowercase)),
+        _ => false,
+    }
+}
+
+/// The changes needed for the articles of `text` to agree with the words
+/// after them, in order. Only articles which are in, or directly followed
+/// by, one of the `changed` byte ranges are looked at, so text which was
+/// left as written keeps its articles.
+pub(crate) fn article_fixes(text: &str, changed: &[Range<usize>]) -> Vec<Edit> {
+    let overlaps = |range: Range<usize>| {
+        changed
+            .iter()
+            .any(|c| c.start < range.end && range.start < c.end)
+    };
+    let tokens = arp_tokenizer::tokenize(text);
+
+    let mut fixes = Vec::new();
+    let mut offset = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let start = offset;
+        offset += token.as_str().len();
+
+        let ArpToken::Word(article) = token else {
+            continue;
+        };
+        let an = match article.to_lowercase().as_str() {
+            "a" => false,
+            "an" => true,
+            _ => continue,
+        };
+        let (ws, next) = match tokens.get(i + 1..i + 3) {
+            Some([ArpToken::Ws(ws), ArpToken::Word(next)]) => (ws, next),
+            _ => continue,
+        };
+        let next_start = offset + ws.len();
+        if !overlaps(start..next_start + next.len()) || is_letter(&tokens, i) {
+            continue;
+        }
+        let in_capitals = in_capitals(&tokens, i);
+        if sounds_like_vowel(next, in_capitals) == an {
+            continue;
+        }
+        let fixed = if an { "a" } else { "an" };
+
+        fixes.push(Edit {
+            range: start..offset,
+            text: match in_capitals {
+                true => fixed.to_uppercase(),
+                false => Casing::of(article).apply(fixed),
+            },
+            origin: Some(Origin::Article),
+        });
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn articles_agree() {
+        assert_eq!(agree_articles("a idiot"), "an idiot");
+        assert_eq!(agree_articles("an knave"), "a knave");
+        assert_eq!(
+            agree_articles("A idiot and AN knave!"),
+            "An idiot and A knave!"
+        );
+        assert_eq!(agree_articles("An puny idiot"), "A puny idiot");
+        assert_eq!(
+            agree_articles("a hour, a honest man"),
+            "an hour, an honest man"
+        );
+        assert_eq!(agree_articles("an unicorn, an one"), "a unicorn, a one");
+        assert_eq!(agree_articles("a umbrella"), "an umbrella");
+        assert_eq!(
+            agree_articles("a uninformed, a onerous"),
+            "an uninformed, an onerous"
+        );
+    }
+
+    #[test]
+    fn unrelated_text_is_borrowed() {
+        assert!(matches!(
+            agree_articles("an idiot, a knave"),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(agree_articles("a, idiot"), Cow::Borrowed(_)));
+        assert!(matches!(agree_articles("banana a"), Cow::Borrowed(_)));
+        assert!(matches!(agree_articles(""), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn initialisms_and_numbers_are_spoken() {
+        assert_eq!(agree_articles("an SMG"), "an SMG");
+        assert_eq!(agree_articles("a SMG"), "an SMG");
+        assert_eq!(agree_articles("an FBI agent"), "an FBI agent");
+        assert_eq!(agree_articles("an MP3 player"), "an MP3 player");
+        assert_eq!(agree_articles("an UFO, an DVD"), "a UFO, a DVD");
+        assert_eq!(agree_articles("an NASA probe"), "a NASA probe");
+        assert_eq!(agree_articles("a 8 year old"), "an 8 year old");
+        assert_eq!(
+            agree_articles("a 11th hour, a 18,000 troops"),
+            "an 11th hour, an 18,000 troops"
+        );
+        assert_eq!(
+            agree_articles("an 110 metre, an 1 in 5"),
+            "a 110 metre, a 1 in 5"
+        );
+    }
+
+    #[test]
+    fn letter_a_is_not_an_article() {
+        assert_eq!(agree_articles("Vitamin A is good"), "Vitamin A is good");
+        assert_eq!(
+            agree_articles("plan A, or plan A idiot"),
+            "plan A, or plan A idiot"
+        );
+        assert_eq!(agree_articles("A idiot. A idiot"), "An idiot. An idiot");
+        assert_eq!(agree_articles("I SAW A IDIOT"), "I SAW AN IDIOT");
+    }
+
+    #[test]
+    fn only_changed_text_is_fixed() {
+        let text = "a idiot and a idiot";
+        let second = 14..19;
+        let fixes = article_fixes(text, &[second]);
+        assert_eq!(apply_edits(text, &fixes), "a idiot and an idiot");
+        assert!(article_fixes(text, &[]).is_empty());
+        // The article itself may be what changed.
+        let article = 0..1;
+        assert_eq!(article_fixes(text, &[article]).len(), 1);
+    }
+}
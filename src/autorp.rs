@@ -1,14 +1,35 @@
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::Range;
+use std::str::FromStr;
 
 use arp_tokenizer::ArpToken;
-use rand::seq::IteratorRandom;
-use rand::{distributions::Standard, thread_rng, Rng};
-use serde::{Deserialize, Serialize};
-use wasm_bindgen::prelude::wasm_bindgen;
+use indexmap::{IndexMap, IndexSet};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{distributions::Standard, thread_rng, Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
 
+use crate::alternatives::{Alternative, Alternatives};
 use crate::arp_tokenizer;
-use crate::template_string::template_evaluate;
+use crate::articles::article_fixes;
+use crate::casing::Casing;
+use crate::error::AutoRPError;
+use crate::explain::{
+    apply_edits, edit_segments, edited_ranges, Edit, Explanation, Origin, Roll, Segment, Span,
+    Utf16Offsets,
+};
+use crate::lint::{self, Shadowed};
+use crate::morphology::Conjugation;
+use crate::pronouns::{archaic_pronoun, AUXILIARIES};
+use crate::rule_index::LazyRuleIndex;
+use crate::sentences::split_sentences;
+use crate::template_string::{
+    check_placeholders, check_templates, template_expand, TemplateMap, DEFAULT_MAX_DEPTH,
+};
 
 pub const AUTORP: &str = include_str!("../resources/Autorp.txt");
 
@@ -18,76 +39,435 @@ pub const AUTORP: &str = include_str!("../resources/Autorp.txt");
 #[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
 #[serde(rename = "autorp.txt")]
 pub struct AutoRP {
-    prepended_words: HashMap<String, String>,
-    appended_words: HashMap<String, String>,
-    word_replacements: HashMap<String, Vec<WordReplacement>>,
+    /// Interjections which may start the text, with their selection weights
+    #[serde(default)]
+    prepended_words: IndexMap<String, u32>,
+    /// Exclamations which may end the text, with their selection weights
+    #[serde(default)]
+    appended_words: IndexMap<String, u32>,
+    #[serde(default)]
+    word_replacements: IndexMap<String, Vec<WordReplacement>>,
+    /// Verb conjugations, tried for words no replacement matched
+    #[serde(default)]
+    conjugations: IndexMap<String, Vec<Conjugation>>,
+    /// Named lists of words which `&name` placeholders in replacements,
+    /// prepended and appended words are filled from
+    #[serde(default)]
+    templates: IndexMap<String, Vec<String>>,
+    /// Whether "you" and "your" are translated by their position in the
+    /// sentence when no rule matches them, `true` if unset
+    #[serde(default)]
+    pronoun_agreement: Option<bool>,
+    /// How many levels of templates within templates are expanded, `8` if unset
+    #[serde(default)]
+    max_template_depth: Option<usize>,
+    /// The rules indexed by the words they match, built when first needed
+    #[serde(skip)]
+    index: LazyRuleIndex,
+    /// The probability of prepending a word to each sentence when asked to, `0.5` if unset
+    #[serde(default)]
+    prepend_probability: Option<f64>,
+    /// The probability of appending a word to each sentence when asked to, `0.5` if unset
+    #[serde(default)]
+    append_probability: Option<f64>,
 }
 
+/// The key under `word_replacements` which holds the list of rules.
+const RULES_KEY: &str = "1";
+
+/// How many times `reroll_segment` tries to pick a translation different
+/// from the current one.
+const REROLL_ATTEMPTS: usize = 16;
+
+/// The prepend and append probability used when none is configured.
+const DEFAULT_FLOURISH_PROBABILITY: f64 = 0.5;
+
 /// Used to provide a default value for serde values that are not present.
 fn default_one() -> u32 {
     1
 }
 
+/// Deserializes a list of phrases, splitting each into its lower case words the
+/// same way the input is tokenized.
+fn phrase_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<String>>, D::Error> {
+    let phrases = Vec::<String>::deserialize(deserializer)?;
+    Ok(phrases.iter().map(|p| phrase_words(p)).collect())
+}
+
+/// Serializes phrases back into the space separated form they are written in.
+fn join_phrases<S: Serializer>(phrases: &[Vec<String>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(phrases.iter().map(|p| p.join(" ")))
+}
+
+/// Splits a phrase into its lower case words, ignoring any punctuation.
+fn phrase_words(phrase: &str) -> Vec<String> {
+    let tokens = arp_tokenizer::tokenize(phrase);
+    tokens
+        .into_iter()
+        .filter_map(|t| match t {
+            ArpToken::Word(w) => Some(w.to_lowercase()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Deserializes a set of words in file order, lower casing each of them so
+/// they can be matched case-insensitively.
+pub(crate) fn lowercase_set<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<IndexSet<String>, D::Error> {
+    let words = Vec::<String>::deserialize(deserializer)?;
+    Ok(words.into_iter().map(|s| s.to_lowercase()).collect())
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
 pub struct WordReplacement {
-    /// Previous word must match
-    #[serde(default)]
-    pub prev: HashSet<String>,
+    /// Previous word must match (lower case)
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub prev: IndexSet<String>,
 
-    /// Word this must match
-    #[serde(default)]
-    pub word: HashSet<String>,
+    /// Word this must match (lower case)
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub word: IndexSet<String>,
 
-    /// Plural word this must match
-    #[serde(default)]
-    pub word_plural: HashSet<String>,
+    /// Plural word this must match (lower case)
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub word_plural: IndexSet<String>,
 
-    /// The chance in which this replacement is applied
+    /// Sequences of words which must match, e.g. `how are you`. Each phrase is
+    /// stored as its lower case words. When present, `prev` and `word` are ignored.
+    #[serde(
+        default,
+        deserialize_with = "phrase_list",
+        serialize_with = "join_phrases"
+    )]
+    pub phrase: Vec<Vec<String>>,
+
+    /// The replacement is applied 1 time in `chance`, so `2` fires half the
+    /// time and `4` a quarter of the time. `0` and `1` always fire.
     #[serde(default = "default_one")]
     pub chance: u32,
 
-    /// A replacement word
+    /// An explicit probability between `0.0` and `1.0` that the replacement is
+    /// applied. Takes precedence over `chance` when present.
+    #[serde(default)]
+    pub probability: Option<f64>,
+
+    /// Rules with a higher priority are tried before those with a lower one,
+    /// whatever they match. `0` if unset.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// The replacements, one of which is picked at random. An entry listed
+    /// twice is picked twice as often.
     #[serde(default)]
-    pub replacement: HashSet<String>,
+    pub replacement: Vec<String>,
 
-    /// A replacement for the plural
+    /// The replacements for the plural, picked like `replacement`
     #[serde(default)]
-    pub replacement_plural: HashSet<String>,
+    pub replacement_plural: Vec<String>,
 
     #[serde(default = "default_one")]
     pub prepend_count: u32,
 
+    /// Words put before the replacement, `prepend_count` of them picked at
+    /// random. An entry listed twice is picked twice as often.
     #[serde(default)]
-    pub replacement_prepend: HashSet<String>,
+    pub replacement_prepend: Vec<String>,
 }
 
-/// A match result which contains the matched word replacement, the kind of match,
-/// and the replacement string.
+/// A match result which contains the index of the matched word replacement,
+/// the kind of match, and the replacement string.
 #[derive(Debug)]
-struct MatchResult<'a> {
-    _matcher: &'a WordReplacement,
-    kind: MatchKind<'a>,
+struct MatchResult {
+    index: usize,
+    kind: MatchKind,
     replacement: String,
+    /// The replacement before its templates were expanded, if it had any
+    template: Option<String>,
+}
+
+/// The names of the templates standing for the matched words, see
+/// `MatchTemplates`.
+const CAPTURES: &[&str] = &["0", "prev"];
+
+/// The templates available to a replacement: `&0` for the word it matched and
+/// `&prev` for the word before that, in lower case, followed by the template
+/// vocabulary.
+struct MatchTemplates<'a> {
+    word: &'a str,
+    prev: Option<&'a str>,
+    templates: &'a IndexMap<String, Vec<String>>,
 }
 
-/// A buffer which contains the previous and current tokens as well as their
-/// translation. Used internally to streamline some of the parser logic.
-#[derive(Debug, Default, PartialEq, Eq)]
-struct AutoRPParserCtx<'a, 'b> {
-    pub prev: &'a str,
-    pub prev_translated: Cow<'b, str>,
-    pub current: &'a str,
-    pub current_translated: Cow<'b, str>,
+impl TemplateMap for MatchTemplates<'_> {
+    fn get(&self, input: &str, rng: &mut dyn RngCore) -> Option<Cow<'_, str>> {
+        let captured = match input {
+            "0" => self.word,
+            "prev" => self.prev.unwrap_or_default(),
+            _ => return TemplateMap::get(self.templates, input, rng),
+        };
+        // Escaped, as the input is never itself a template.
+        Some(Cow::Owned(captured.replace('&', "&&")))
+    }
+}
+
+/// A word which has already been written to the output, but which may still be
+/// replaced by a multi-word match ending at a later word.
+#[derive(Debug)]
+struct WindowWord {
+    /// The word as it appears in the input
+    source: String,
+    /// Where the word starts in the input
+    src_start: usize,
+    /// The lower case form used for matching
+    lower: String,
+    /// Where the word's translation starts in the output
+    out_start: usize,
+    /// Whether an earlier multi-word match merged this word with the one
+    /// before it. A new match may not start at such a word.
+    joined: bool,
+}
+
+/// The words since the last punctuation, most recent last. Used internally to
+/// match phrases and previous words.
+#[derive(Debug, Default)]
+struct AutoRPParserCtx {
+    words: Vec<WindowWord>,
+    /// The most words any rule can match at once
+    max_len: usize,
+}
+
+impl AutoRPParserCtx {
+    fn new(max_len: usize) -> Self {
+        Self {
+            words: Vec::with_capacity(max_len),
+            max_len,
+        }
+    }
+
+    /// Pushes a new word, forgetting the oldest one if it can no longer be
+    /// part of any match.
+    fn push(&mut self, source: &str, src_start: usize, out_start: usize) {
+        if self.words.len() >= self.max_len.max(1) {
+            self.words.remove(0);
+        }
+        self.words.push(WindowWord {
+            source: source.to_string(),
+            src_start,
+            lower: source.to_lowercase(),
+            out_start,
+            joined: false,
+        });
+    }
+
+    /// Returns `true` if a match covering the last `len` words would not cut
+    /// through the middle of an earlier multi-word match.
+    fn fits(&self, len: usize) -> bool {
+        len <= self.words.len() && !self.words[self.words.len() - len].joined
+    }
+
+    /// Records that the last `len` words were replaced as a whole, returning
+    /// the first of them.
+    fn join(&mut self, len: usize) -> &WindowWord {
+        let start = self.words.len() - len;
+        let out_start = self.words[start].out_start;
+        for word in &mut self.words[start + 1..] {
+            word.joined = true;
+            word.out_start = out_start;
+        }
+        &self.words[start]
+    }
+
+    /// The last word written for the word before the current one, in lower
+    /// case, or `None` if the current word starts the context.
+    fn previous_output(&self, buf: &str) -> Option<String> {
+        let [.., prev, current] = self.words.as_slice() else {
+            return None;
+        };
+        buf[prev.out_start..current.out_start]
+            .split(|c: char| !c.is_alphanumeric() && !arp_tokenizer::is_joiner(c))
+            .rfind(|w| !w.is_empty())
+            .map(str::to_lowercase)
+    }
+
+    /// The lower case words, oldest first.
+    fn lower(&self) -> Vec<&str> {
+        self.words.iter().map(|w| w.lower.as_str()).collect()
+    }
+
+    fn clear(&mut self) {
+        self.words.clear();
+    }
+}
+
+/// Translates the words of an input which may arrive a piece at a time,
+/// keeping the words which can still be matched along with later ones.
+struct WordTranslator<'r> {
+    arp: &'r AutoRP,
+    ctx: AutoRPParserCtx,
+    rolls: Vec<Roll>,
+    /// The output so far, without articles made to agree with it
+    buf: String,
+    /// How much of the input has been translated
+    offset: usize,
 }
 
-impl<'a, 'b> AutoRPParserCtx<'a, 'b> {
-    /// Updates the context by shifting the current to previous and then
-    /// setting the new values to `current`.
-    fn update(&mut self, next: &'a str, next_translated: Cow<'b, str>) {
-        self.prev = self.current;
-        self.prev_translated = self.current_translated.clone();
-        self.current = next;
-        self.current_translated = next_translated;
+impl<'r> WordTranslator<'r> {
+    fn new(arp: &'r AutoRP, capacity: usize) -> Self {
+        let max_len = arp.index.get(arp.rules()).max_len();
+        Self {
+            arp,
+            // Conjugations look at the two words before the current one.
+            ctx: AutoRPParserCtx::new(max_len.max(3)),
+            rolls: Vec::new(),
+            buf: String::with_capacity(capacity),
+            offset: 0,
+        }
+    }
+
+    /// Translates the first `count` of `tokens`, which come right after
+    /// those translated before. The tokens after them are only looked at, to
+    /// find the word following the last one. Every change made is added to
+    /// `spans`, if given.
+    fn translate(
+        &mut self,
+        tokens: &[ArpToken],
+        count: usize,
+        rng: &mut impl Rng,
+        mut spans: Option<&mut Vec<Span>>,
+    ) {
+        let arp = self.arp;
+        for (i, token) in tokens[..count].iter().enumerate() {
+            let src_start = self.offset;
+            self.offset += token.as_str().len();
+            self.rolls.clear();
+
+            let current = match token {
+                ArpToken::Ws(s) => {
+                    self.buf.push_str(s);
+                    continue;
+                }
+                ArpToken::Punct(s) => {
+                    // Punctuation ends the context of the previous words, so
+                    // only rules for the punctuation itself can match.
+                    self.ctx.clear();
+                    let out_start = self.buf.len();
+                    let mr = arp.match_on_nodes(&[s], |_| true, &mut self.rolls, rng);
+                    self.buf
+                        .push_str(mr.as_ref().map_or(*s, |mr| &mr.replacement));
+                    if let Some(spans) = spans.as_deref_mut() {
+                        spans.extend(explain_match(
+                            mr,
+                            src_start..self.offset,
+                            out_start..self.buf.len(),
+                            &self.rolls,
+                        ));
+                    }
+                    continue;
+                }
+                ArpToken::Word(s) => s,
+            };
+
+            self.ctx.push(current, src_start, self.buf.len());
+            let ctx = &self.ctx;
+            let mr =
+                match arp.match_on_nodes(&ctx.lower(), |len| ctx.fits(len), &mut self.rolls, rng) {
+                    None => {
+                        let next = match tokens.get(i + 1..i + 3) {
+                            Some([ArpToken::Ws(_), ArpToken::Word(next)]) => Some(*next),
+                            _ => None,
+                        };
+                        let (word, origin) = match arp.pronoun(&mut self.ctx, next, &mut self.buf) {
+                            Some((pronoun, verb)) => {
+                                if let (Some(spans), Some(len), [.., prev, _]) =
+                                    (spans.as_deref_mut(), verb, self.ctx.words.as_slice())
+                                {
+                                    spans.push(Span {
+                                        source: prev.src_start..prev.src_start + prev.source.len(),
+                                        output: prev.out_start..prev.out_start + len,
+                                        origin: Origin::Conjugation,
+                                        rolls: Vec::new(),
+                                        template: None,
+                                        source_utf16: None,
+                                        output_utf16: None,
+                                    });
+                                }
+                                (Some(pronoun.to_string()), Origin::Pronoun)
+                            }
+                            None => (
+                                arp.conjugate(&self.ctx, &self.buf, next),
+                                Origin::Conjugation,
+                            ),
+                        };
+
+                        let out_start = self.buf.len();
+                        let word = word.map(|word| Casing::of(current).apply(&word));
+                        self.buf.push_str(word.as_deref().unwrap_or(current));
+                        let changed = word.as_deref().is_some_and(|word| word != *current);
+                        if let Some(spans) = spans.as_deref_mut() {
+                            if changed || !self.rolls.is_empty() {
+                                spans.push(Span {
+                                    source: src_start..self.offset,
+                                    output: out_start..self.buf.len(),
+                                    origin: if changed { origin } else { Origin::Kept },
+                                    rolls: self.rolls.clone(),
+                                    template: None,
+                                    source_utf16: None,
+                                    output_utf16: None,
+                                });
+                            }
+                        }
+                        continue;
+                    }
+                    Some(mr) => mr,
+                };
+
+            // A multi-word match replaces every word it covers, along with the
+            // whitespace between them, and takes the casing of the first one.
+            // The words stay in the context so that a longer phrase ending
+            // later can still replace them in turn.
+            let first = self.ctx.join(mr.kind.len());
+            self.buf.truncate(first.out_start);
+            self.buf
+                .push_str(&Casing::of(&first.source).apply(&mr.replacement));
+            if let Some(spans) = spans.as_deref_mut() {
+                // The words covered lose the spans of their own translations.
+                spans.retain(|span| span.source.start < first.src_start);
+                spans.extend(explain_match(
+                    Some(mr),
+                    first.src_start..self.offset,
+                    first.out_start..self.buf.len(),
+                    &self.rolls,
+                ));
+            }
+        }
+    }
+
+    /// How much of the output can no longer change, as no later match can
+    /// reach back into it.
+    fn settled(&self) -> usize {
+        self.ctx
+            .words
+            .first()
+            .map_or(self.buf.len(), |w| w.out_start)
+    }
+
+    /// Removes the first `len` bytes of the output, and returns them along
+    /// with the spans within them. Only at the end of the input may `len` go
+    /// past the settled output, as the words there are forgotten.
+    fn take(&mut self, len: usize, spans: &mut Vec<Span>) -> (String, Vec<Span>) {
+        self.ctx.words.retain(|word| word.out_start >= len);
+        for word in &mut self.ctx.words {
+            word.out_start -= len;
+        }
+        let (taken, rest): (Vec<Span>, Vec<Span>) =
+            spans.drain(..).partition(|span| span.output.start < len);
+        *spans = rest;
+        for span in spans.iter_mut() {
+            span.output = span.output.start - len..span.output.end - len;
+        }
+        (self.buf.drain(..len).collect(), taken)
     }
 }
 
@@ -102,6 +482,12 @@ impl<'a, 'b> AutoRPParserCtx<'a, 'b> {
 /// - `append`: Appends a word to the string based on certain conditions.
 /// - `translate_postprocess`: Translates the input string and optionally prepends/appends words.
 /// - `translate`: Translates the input string using the `AutoRP` instance.
+/// - `translate_seeded`, `translate_postprocess_seeded`: Same as above, but reproducible for a given seed.
+/// - `translate_with_rng`, `translate_postprocess_with_rng`: Same as above, using the given RNG.
+/// - `translate_explained`: Translates the input string and explains which rules changed it.
+/// - `translate_segments`, `reroll_segment`: Splits a translation into segments, and re-rolls one.
+/// - `alternatives`: Lists every rule matching each word of the input, with all its replacements.
+/// - `shadowed_rules`: Lists the rules which are never applied, because others always match first.
 ///
 /// # Example
 /// ```
@@ -112,301 +498,1309 @@ impl<'a, 'b> AutoRPParserCtx<'a, 'b> {
 /// ```
 #[wasm_bindgen]
 impl AutoRP {
-    /// Matches nodes based on previous and current words, and returns a `MatchResult`.
-    fn match_on_nodes<'a>(
-        &'a self,
-        prev: &'a str,
-        word: &'a str,
+    /// Matches nodes against the most recent words, and returns a `MatchResult`.
+    /// The words are expected to already be lower case, with the current word last.
+    ///
+    /// Rules are tried by their `precedence`: highest priority first, then
+    /// most specific match, then in file order. `fits` is asked whether a
+    /// match covering a given number of words may be used. The dice rolled for
+    /// rules with a chance are added to `rolls`.
+    fn match_on_nodes(
+        &self,
+        words: &[&str],
+        fits: impl Fn(usize) -> bool,
+        rolls: &mut Vec<Roll>,
         rng: &mut impl Rng,
-    ) -> Option<MatchResult<'a>> {
-        self.word_replacements["1"]
-            .iter()
-            .flat_map(|n| {
-                let res: Option<(MatchKind<'_>, String)> = n.translate(prev, word, rng);
-                match res {
-                    Some((kind, replacement)) => match n.is_chance(rng) {
-                        true => Some(MatchResult {
-                            _matcher: n,
-                            kind,
-                            replacement,
-                        }),
-                        false => None,
-                    },
-                    None => None,
-                }
+    ) -> Option<MatchResult> {
+        let rules = self.rules();
+        let mut candidates: Vec<(usize, MatchKind)> = self
+            .index
+            .get(rules)
+            .candidates(words)
+            .into_iter()
+            .filter_map(|i| rules[i].matches(words).map(|kind| (i, kind)))
+            .filter(|(_, kind)| fits(kind.len()))
+            .collect();
+        candidates.sort_by_key(|&(i, kind)| rules[i].precedence(kind));
+
+        let captures = MatchTemplates {
+            word: words.last().copied().unwrap_or_default(),
+            prev: words.len().checked_sub(2).map(|i| words[i]),
+            templates: &self.templates,
+        };
+
+        // A rule whose roll fails does not stop the search, so the next
+        // matching rule still gets its chance.
+        candidates.into_iter().find_map(|(index, kind)| {
+            let n = &rules[index];
+            let probability = n.probability();
+            let passed = n.is_chance(rng);
+            if probability < 1. {
+                rolls.push(Roll {
+                    rule: index,
+                    probability,
+                    passed,
+                });
+            }
+            if !passed {
+                return None;
+            }
+
+            let replacement = n.replace(&kind, rng);
+            let (replacement, template) = match self.expand_templates(&replacement, &captures, rng)
+            {
+                Cow::Owned(expanded) => (expanded, Some(replacement)),
+                Cow::Borrowed(_) => (replacement, None),
+            };
+            Some(MatchResult {
+                index,
+                kind,
+                replacement,
+                template,
             })
-            .next()
+        })
     }
 
     /// Returns a new `AutoRP` instance with the default values.
+    #[allow(clippy::should_implement_trait)]
     #[wasm_bindgen]
     pub fn default() -> Self {
-        keyvalues_serde::from_str::<AutoRP>(AUTORP).unwrap()
+        AutoRP::from_str(AUTORP).expect("the built-in Autorp.txt is valid")
+    }
+
+    /// Loads an `AutoRP` from the text of a rules file, throwing an `Error`
+    /// describing the problem if it is invalid.
+    #[wasm_bindgen]
+    pub fn from_rules(rules: &str) -> Result<AutoRP, JsError> {
+        AutoRP::from_str(rules).map_err(|err| JsError::new(&err.to_string()))
     }
 
-    /// Generate a prepended word 50% of the time.
-    fn prepend(&self, rng: &mut impl Rng) -> Cow<str> {
-        if self.prepended_words.is_empty() || rng.gen::<bool>() {
+    /// Generate a prepended word with the configured prepend probability.
+    fn prepend(&self, rng: &mut impl Rng) -> Cow<'_, str> {
+        if self.prepended_words.is_empty() || !rng.gen_bool(self.prepend_probability()) {
             return Default::default();
         }
 
-        Cow::Borrowed(self.prepended_words.keys().choose(rng).unwrap())
+        choose_weighted(&self.prepended_words, rng).map_or(Cow::default(), Cow::Borrowed)
     }
 
-    /// Generate an appended word 50% of the time.
-    fn append(&self, rng: &mut impl Rng) -> Cow<str> {
-        if self.appended_words.is_empty() || rng.gen::<bool>() {
+    /// Generate an appended word with the configured append probability.
+    fn append(&self, rng: &mut impl Rng) -> Cow<'_, str> {
+        if self.appended_words.is_empty() || !rng.gen_bool(self.append_probability()) {
             return Default::default();
         }
 
-        Cow::Borrowed(self.appended_words.keys().choose(rng).unwrap())
+        choose_weighted(&self.appended_words, rng).map_or(Cow::default(), Cow::Borrowed)
     }
 
-    /// Translates the input string and optionally prepends/appends words.
+    /// The probability that a word is prepended to each sentence when prepending is requested.
     #[wasm_bindgen]
-    pub fn translate_postprocess(&self, input: &str, prepend: bool, append: bool) -> String {
-        let translated = self.translate(input);
+    pub fn prepend_probability(&self) -> f64 {
+        self.prepend_probability
+            .filter(|p| !p.is_nan())
+            .unwrap_or(DEFAULT_FLOURISH_PROBABILITY)
+            .clamp(0., 1.)
+    }
 
-        if !prepend && !append {
-            return translated;
-        }
+    /// The probability that a word is appended to each sentence when appending is requested.
+    #[wasm_bindgen]
+    pub fn append_probability(&self) -> f64 {
+        self.append_probability
+            .filter(|p| !p.is_nan())
+            .unwrap_or(DEFAULT_FLOURISH_PROBABILITY)
+            .clamp(0., 1.)
+    }
 
-        let mut rng = thread_rng();
+    /// Sets the probability, between `0.0` and `1.0`, that a word is prepended
+    /// to each sentence when prepending is requested.
+    #[wasm_bindgen]
+    pub fn set_prepend_probability(&mut self, probability: f64) {
+        self.prepend_probability = Some(probability);
+    }
 
-        let evaluated = template_evaluate(&translated, &self.word_replacements["1"]);
+    /// Sets the probability, between `0.0` and `1.0`, that a word is appended
+    /// to each sentence when appending is requested.
+    #[wasm_bindgen]
+    pub fn set_append_probability(&mut self, probability: f64) {
+        self.append_probability = Some(probability);
+    }
 
-        let prepend = if prepend {
-            self.prepend(&mut rng)
-        } else {
-            Cow::default()
-        };
-        let prepend = template_evaluate(&prepend, &self.word_replacements["1"]);
+    /// Whether "you" and "your" are translated by their position in the
+    /// sentence when no rule matches them.
+    #[wasm_bindgen]
+    pub fn pronoun_agreement(&self) -> bool {
+        self.pronoun_agreement.unwrap_or(true)
+    }
 
-        let append = if append && translated.ends_with(|c: char| c.is_ascii_punctuation()) {
-            format!(" {}", self.append(&mut rng))
-        } else {
-            String::default()
-        };
-        let append = template_evaluate(&append, &self.word_replacements["1"]);
+    /// Enables or disables translating "you" and "your" by their position in
+    /// the sentence.
+    #[wasm_bindgen]
+    pub fn set_pronoun_agreement(&mut self, enabled: bool) {
+        self.pronoun_agreement = Some(enabled);
+    }
 
-        // If nothing was changed, return the buf as-is.
-        if prepend.is_empty() && matches!(evaluated, Cow::Borrowed(_)) && append.is_empty() {
-            return translated;
-        }
+    /// How many levels of templates within templates are expanded.
+    #[wasm_bindgen]
+    pub fn max_template_depth(&self) -> usize {
+        self.max_template_depth.unwrap_or(DEFAULT_MAX_DEPTH)
+    }
+
+    /// Sets the selection weight of a prepended word, adding it if it is new.
+    /// A weight of `0` keeps the word from ever being chosen.
+    #[wasm_bindgen]
+    pub fn set_prepended_weight(&mut self, word: &str, weight: u32) {
+        self.prepended_words.insert(word.to_string(), weight);
+    }
+
+    /// Sets the selection weight of an appended word, adding it if it is new.
+    /// A weight of `0` keeps the word from ever being chosen.
+    #[wasm_bindgen]
+    pub fn set_appended_weight(&mut self, word: &str, weight: u32) {
+        self.appended_words.insert(word.to_string(), weight);
+    }
+
+    /// Translates the input string and optionally prepends/appends words.
+    #[wasm_bindgen]
+    pub fn translate_postprocess(&self, input: &str, prepend: bool, append: bool) -> String {
+        self.translate_postprocess_with_rng(input, prepend, append, &mut thread_rng())
+    }
 
-        format!("{}{}{}", prepend, evaluated, append)
+    /// Like `translate_postprocess`, but every random choice is drawn from an
+    /// RNG seeded with `seed`, so the same seed always gives the same output.
+    #[wasm_bindgen]
+    pub fn translate_postprocess_seeded(
+        &self,
+        input: &str,
+        prepend: bool,
+        append: bool,
+        seed: u64,
+    ) -> String {
+        self.translate_postprocess_with_rng(
+            input,
+            prepend,
+            append,
+            &mut StdRng::seed_from_u64(seed),
+        )
     }
 
     /// Translates the input string using the `AutoRP` instance.
     #[wasm_bindgen]
     pub fn translate(&self, input: &str) -> String {
-        let mut rng = thread_rng();
-        let mut buf = String::with_capacity(1024);
-        let (_, tokens) = arp_tokenizer::parse(input).unwrap();
-        let mut it = tokens.iter();
+        self.translate_with_rng(input, &mut thread_rng())
+    }
 
-        let mut ctx: AutoRPParserCtx = Default::default();
-        let mut wsbuf: Vec<&str> = Vec::with_capacity(64);
+    /// Like `translate`, but every random choice is drawn from an RNG seeded
+    /// with `seed`, so the same seed always gives the same output.
+    #[wasm_bindgen]
+    pub fn translate_seeded(&self, input: &str, seed: u64) -> String {
+        self.translate_with_rng(input, &mut StdRng::seed_from_u64(seed))
+    }
 
-        while let Some(token) = it.next() {
-            let current = match token {
-                ArpToken::Ws(s) => {
-                    wsbuf.push(s);
-                    continue;
-                }
-                ArpToken::Word(s) => s,
-            };
+    /// Translates the input string, and explains which rules changed which
+    /// parts of it.
+    #[wasm_bindgen]
+    pub fn translate_explained(&self, input: &str) -> Explanation {
+        self.translate_explained_with_rng(input, &mut thread_rng())
+    }
 
-            match self.match_on_nodes(ctx.current, current, &mut rng) {
-                None => {
-                    ctx.update(&current, Cow::from(*current));
+    /// Like `translate_explained`, but reproducible for a given seed.
+    #[wasm_bindgen]
+    pub fn translate_explained_seeded(&self, input: &str, seed: u64) -> Explanation {
+        self.translate_explained_with_rng(input, &mut StdRng::seed_from_u64(seed))
+    }
 
-                    buf.push_str(&ctx.prev_translated);
-                    buf.push_str(&wsbuf.join(""));
-                    wsbuf.clear();
-                }
-                Some(mr) => {
-                    ctx.update(&current, mr.replacement.into());
-                    if matches!(mr.kind, MatchKind::Previous(..)) {
-                        wsbuf.pop();
-                        buf.push_str(&wsbuf.join(""));
-                        wsbuf.clear();
-                        buf.push_str(&ctx.current_translated);
-                        ctx = Default::default();
-                        continue;
-                    }
+    /// Like `translate_postprocess`, but also explains which rules changed
+    /// which parts of the text.
+    #[wasm_bindgen]
+    pub fn translate_postprocess_explained(
+        &self,
+        input: &str,
+        prepend: bool,
+        append: bool,
+    ) -> Explanation {
+        self.translate_postprocess_explained_with_rng(input, prepend, append, &mut thread_rng())
+    }
 
-                    buf.push_str(&ctx.prev_translated);
+    /// Translates the input string like `translate_postprocess`, split into
+    /// the segments which were changed and the text between them.
+    #[wasm_bindgen]
+    pub fn translate_segments(&self, input: &str, prepend: bool, append: bool) -> Vec<Segment> {
+        self.translate_postprocess_explained(input, prepend, append)
+            .segments(input)
+    }
+
+    /// Picks a different translation for the segment at `index`, leaving
+    /// the others as they are. See `reroll_segment_with_rng`.
+    #[wasm_bindgen]
+    pub fn reroll_segment(&self, segments: Vec<Segment>, index: usize) -> Vec<Segment> {
+        self.reroll_segment_with_rng(&segments, index, &mut thread_rng())
+    }
+
+    /// Lists every rule matching each word and punctuation mark of the
+    /// input, with all the replacements it could choose from, regardless of
+    /// chance. Words which no rule matches are left out.
+    #[wasm_bindgen]
+    pub fn alternatives(&self, input: &str) -> Vec<Alternatives> {
+        let rules = self.rules();
+        let index = self.index.get(rules);
+        let tokens = arp_tokenizer::tokenize(input);
+        let mut utf16 = Utf16Offsets::new(input);
+
+        let mut res = Vec::new();
+        // The words since the last punctuation, with where they start
+        let mut words: Vec<(usize, String)> = Vec::new();
+        let mut offset = 0;
 
-                    buf.push_str(&wsbuf.join(""));
-                    wsbuf.clear();
+        for token in &tokens {
+            let start = offset;
+            offset += token.as_str().len();
+            match token {
+                ArpToken::Ws(_) => continue,
+                // Rules for punctuation match it on its own.
+                ArpToken::Punct(_) => words.clear(),
+                ArpToken::Word(_) if words.len() >= index.max_len().max(1) => {
+                    words.remove(0);
                 }
-            };
+                ArpToken::Word(_) => {}
+            }
+            words.push((start, token.as_str().to_lowercase()));
+
+            let lower: Vec<&str> = words.iter().map(|(_, w)| w.as_str()).collect();
+            let mut alternatives: Vec<Alternative> = index
+                .candidates(&lower)
+                .into_iter()
+                .filter_map(|i| {
+                    let n = &rules[i];
+                    let kind = n.matches(&lower)?;
+                    let replacements = match kind {
+                        MatchKind::Plural => &n.replacement_plural,
+                        _ => &n.replacement,
+                    };
+                    let source = words[words.len() - kind.len()].0..offset;
+                    Some(Alternative {
+                        rule: i,
+                        kind,
+                        source_utf16: utf16.range(&source),
+                        source,
+                        probability: n.probability(),
+                        replacements: replacements.clone(),
+                        prepend: n.replacement_prepend.clone(),
+                        prepend_count: n.prepend_count,
+                    })
+                })
+                .collect();
+            alternatives.sort_by_key(|a| rules[a.rule].precedence(a.kind));
+
+            if matches!(token, ArpToken::Punct(_)) {
+                words.clear();
+            }
+            if !alternatives.is_empty() {
+                res.push(Alternatives {
+                    source: start..offset,
+                    source_utf16: utf16.range(&(start..offset)),
+                    rules: alternatives,
+                });
+            }
         }
-        buf.push_str(&wsbuf.join(""));
-        buf.push_str(&ctx.current_translated);
-        buf
+        res
     }
 }
 
-/// A trait which allows for choosing a random element from a set.
-trait SetChoose<T> {
-    fn choose<R>(&self, rng: &mut R) -> &T
-    where
-        R: Rng + Sized;
-}
+impl FromStr for AutoRP {
+    type Err = AutoRPError;
 
-/// Implements the `SetChoose` trait for `HashSet`.
-impl<T> SetChoose<T> for HashSet<T> {
-    fn choose<R>(&self, rng: &mut R) -> &T
-    where
-        R: Rng + Sized,
-    {
-        self.iter().choose(rng).unwrap()
+    /// Parses and validates the text of a rules file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let autorp = keyvalues_serde::from_str::<AutoRP>(s)?;
+        autorp.validate()?;
+        autorp.index.get(autorp.rules());
+        Ok(autorp)
     }
 }
 
-/// The different kinds of matches that can occur.
-/// * `Previous` is used to match the previous word and the current word.
-/// * `Word` is used to match the current word.
-/// * `Plural` is used to match the plural of the current word.
-#[allow(dead_code)]
-#[derive(Debug)]
-enum MatchKind<'a> {
-    /// Previous word must match
-    Previous(&'a str, &'a str),
-    /// word match
-    Word(&'a str),
-    /// plural word match
-    Plural(&'a str),
-}
+impl AutoRP {
+    /// Reads, parses and validates a rules file.
+    pub fn from_reader(reader: impl Read) -> Result<Self, AutoRPError> {
+        let autorp = Self::from_reader_unvalidated(reader)?;
+        autorp.validate()?;
+        autorp.index.get(autorp.rules());
+        Ok(autorp)
+    }
 
-impl WordReplacement {
-    /// Returns `true` if the chance is 1 or if the random number is less than the chance.
-    /// Otherwise, returns `false`.
-    fn is_chance(&self, rng: &mut impl Rng) -> bool {
-        if self.chance == 1 {
-            return true;
-        }
-        let rand: f32 = rng.sample(Standard);
-        let chance = 1. / self.chance as f32;
+    /// Reads and parses a rules file without validating it, for rules which
+    /// only make sense once merged over others, such as a file adding just
+    /// `prepended_words`. Call `validate` on the merged result before use.
+    pub fn from_reader_unvalidated(reader: impl Read) -> Result<Self, AutoRPError> {
+        Ok(keyvalues_serde::from_reader::<_, AutoRP>(reader)?)
+    }
 
-        chance < rand
+    /// Returns a builder starting from the built-in dictionary.
+    pub fn builder() -> AutoRPBuilder {
+        AutoRPBuilder::from(AutoRP::default())
     }
 
-    /// Returns a `MatchKind` if the current and next words match the `WordReplacement`.
-    fn matches<'a>(&self, current: &'a str, next: &'a str) -> Option<MatchKind<'a>> {
-        if !self.prev.is_empty() {
-            if self.prev.contains(current) && self.word.contains(next) {
-                return Some(MatchKind::Previous(current, next));
-            }
-            return None;
+    /// The words which may be prepended, with their selection weights.
+    pub fn prepended_words(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.prepended_words.iter().map(|(w, &n)| (w.as_str(), n))
+    }
+
+    /// The words which may be appended, with their selection weights.
+    pub fn appended_words(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.appended_words.iter().map(|(w, &n)| (w.as_str(), n))
+    }
+
+    /// Merges `other` over this instance. Its rules and conjugations are tried
+    /// before the existing ones, its prepended and appended words and template
+    /// lists are added to ours, and any probabilities it sets replace ours.
+    pub fn merge(&mut self, other: AutoRP) {
+        for (name, values) in other.templates {
+            self.templates.entry(name).or_default().extend(values);
         }
-        if self.word.contains(next) {
-            return Some(MatchKind::Word(next));
+        self.prepended_words.extend(other.prepended_words);
+        self.appended_words.extend(other.appended_words);
+        self.pronoun_agreement = other.pronoun_agreement.or(self.pronoun_agreement);
+        self.max_template_depth = other.max_template_depth.or(self.max_template_depth);
+        self.prepend_probability = other.prepend_probability.or(self.prepend_probability);
+        self.append_probability = other.append_probability.or(self.append_probability);
+        self.index.reset();
+        for (key, mut rules) in other.word_replacements {
+            let existing = self.word_replacements.entry(key).or_default();
+            rules.append(existing);
+            *existing = rules;
         }
-        if self.word_plural.contains(next) {
-            return Some(MatchKind::Plural(next));
+        for (key, mut conjugations) in other.conjugations {
+            let existing = self.conjugations.entry(key).or_default();
+            conjugations.append(existing);
+            *existing = conjugations;
         }
-        return None;
     }
 
-    pub fn simple_get(&self, current: &str, rng: &mut impl Rng) -> Option<&str> {
-        if self.word.contains(current) {
-            return self.replacement.iter().choose(rng).map(|s| s.as_str());
-        }
-        if self.word_plural.contains(current) {
-            return self
-                .replacement_plural
+    /// Checks that the rules can be used for translation without panicking,
+    /// and that every template can be expanded and every placeholder names one.
+    ///
+    /// Rules are checked when they are loaded, but merging rules files may
+    /// combine templates in ways neither file contained on its own.
+    pub fn validate(&self) -> Result<(), AutoRPError> {
+        check_templates(&self.templates, self.max_template_depth())?;
+        let template = |name: &str| self.templates.contains_key(name);
+        let capture_or_template = |name: &str| CAPTURES.contains(&name) || template(name);
+        self.prepended_words
+            .keys()
+            .chain(self.appended_words.keys())
+            .try_for_each(|text| check_placeholders(text, template))?;
+
+        let rules = self
+            .word_replacements
+            .get(RULES_KEY)
+            .ok_or(AutoRPError::MissingSection("word_replacements"))?;
+
+        for (i, rule) in rules.iter().enumerate() {
+            let empty_word = rule
+                .word
+                .iter()
+                .chain(rule.prev.iter())
+                .cloned()
+                .chain(rule.phrase.iter().map(|p| p.join(" ")))
+                .next()
+                .filter(|_| rule.replacement.is_empty());
+            let empty_plural = rule
+                .word_plural
+                .iter()
+                .next()
+                .filter(|_| rule.replacement_plural.is_empty())
+                .cloned();
+
+            if let Some(word) = empty_word.or(empty_plural) {
+                return Err(AutoRPError::EmptyReplacements { rule: i, word });
+            }
+
+            rule.replacement
                 .iter()
-                .choose(rng)
-                .map(|s| s.as_str());
+                .chain(&rule.replacement_plural)
+                .chain(&rule.replacement_prepend)
+                .try_for_each(|text| check_placeholders(text, capture_or_template))?;
         }
-        None
+
+        Ok(())
+    }
+
+    /// Finds the rules which are never applied, because whatever words they
+    /// match, a rule which is always applied matches them too and is tried
+    /// first. Such rules are usually mistakes, e.g. a `"you"` rule placed
+    /// above one for `"thank you"` with a higher priority.
+    pub fn shadowed_rules(&self) -> Vec<Shadowed> {
+        let rules = self.rules();
+        lint::shadowed_rules(rules, self.index.get(rules))
     }
 
-    /// Translates the current and next words if they match the `WordReplacement`.
-    fn translate<'a>(
+    /// Expands the templates in `text`. Templates are checked when the rules
+    /// are loaded, so expansion can only fail for rules which were merged or
+    /// built by hand without being validated; their text is left as is.
+    fn expand_templates<'a>(
         &self,
-        current: &'a str,
-        next: &'a str,
+        text: &'a str,
+        map: &(impl TemplateMap + ?Sized),
         rng: &mut impl Rng,
-    ) -> Option<(MatchKind<'a>, String)> {
-        match self.matches(current, next) {
-            None => return None,
-            Some(kind) => {
-                let replacement = match kind {
-                    MatchKind::Previous(_, _) | MatchKind::Word(_) => self.replacement.choose(rng),
-                    MatchKind::Plural(_) => self.replacement_plural.choose(rng),
-                };
+    ) -> Cow<'a, str> {
+        template_expand(text, map, self.max_template_depth(), rng).unwrap_or(Cow::Borrowed(text))
+    }
 
-                Some((kind, self.prepend(rng) + replacement))
+    /// Conjugates the current word of `ctx` after the subject written to
+    /// `buf` before it, and before the `next` word of the input, if any.
+    fn conjugate(&self, ctx: &AutoRPParserCtx, buf: &str, next: Option<&str>) -> Option<String> {
+        // In a question such as "did you see", the auxiliary before the
+        // subject is what agrees with it, and the verb keeps its base form.
+        if let [.., auxiliary, _, _] = ctx.words.as_slice() {
+            if AUXILIARIES.contains(&auxiliary.lower.as_str()) {
+                return None;
             }
         }
+        let subject = ctx.previous_output(buf)?;
+        let next = next.map(str::to_lowercase);
+        self.conjugate_after(&subject, &ctx.words.last()?.lower, next.as_deref())
     }
 
-    /// Prepends the replacement word with a random number of words from the `replacement_prepend` set.
-    /// The number of words is determined by the `prepend_count` field.
-    /// If `replacement_prepend` is empty, returns an empty string.
-    /// Otherwise, returns the joined string with a comma and space.
-    fn prepend(&self, rng: &mut impl Rng) -> String {
-        if self.replacement_prepend.is_empty() {
-            return String::default();
-        }
-        let mut res: Vec<&str> = Vec::with_capacity(self.prepend_count as usize);
-        for _ in 0..self.prepend_count {
-            res.push(self.replacement_prepend.choose(rng));
-        }
-        res.join(", ").to_string() + " "
+    /// Conjugates `word` after `subject` and before `next` using the first
+    /// conjugation that applies.
+    fn conjugate_after(&self, subject: &str, word: &str, next: Option<&str>) -> Option<String> {
+        self.conjugations
+            .get(RULES_KEY)?
+            .iter()
+            .find_map(|c| c.conjugate(subject, word, next))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Chooses the archaic form of the current word of `ctx` if it is a second
+    /// person pronoun and pronoun agreement is enabled.
+    ///
+    /// When the choice is "thou" and the word before it is an untranslated
+    /// auxiliary, as in "are you", that word is conjugated to agree with it
+    /// ("art thou") in `buf`. The length of the conjugated word is returned
+    /// along with the pronoun if so.
+    fn pronoun(
+        &self,
+        ctx: &mut AutoRPParserCtx,
+        next: Option<&str>,
+        buf: &mut String,
+    ) -> Option<(&'static str, Option<usize>)> {
+        if !self.pronoun_agreement() {
+            return None;
+        }
 
-    #[test]
-    fn previous_works() {
-        let wrp = WordReplacement {
-            prev: HashSet::from(["foo".to_string()]),
-            word: HashSet::from(["bar".to_string()]),
-            replacement: HashSet::from(["foobar".to_string()]),
-            ..Default::default()
+        let next = next.map(str::to_lowercase);
+        let (prev, current) = match ctx.words.as_mut_slice() {
+            [.., prev, current] => (Some(prev), current),
+            [current] => (None, current),
+            [] => return None,
+        };
+        let prev_lower = prev.as_ref().map(|p| p.lower.as_str());
+        let pronoun = archaic_pronoun(prev_lower, &current.lower, next.as_deref())?;
+        let Some(prev) = prev else {
+            return Some((pronoun, None));
         };
 
-        assert!(matches!(
-            wrp.matches("foo", "bar"),
-            Some(MatchKind::Previous(_, _))
-        ));
-        assert!(matches!(wrp.matches("", "bar"), None));
-        assert!(matches!(wrp.matches("foo", ""), None));
+        // The whole of the previous word's output, up to the whitespace
+        // before the current word, must be the word as written, so that a
+        // replacement merely starting with it is not conjugated over.
+        let prev_end = prev.out_start + prev.source.len();
+        let untouched = !current.joined
+            && buf
+                .get(prev.out_start..current.out_start)
+                .map(str::trim_end)
+                == Some(prev.source.as_str());
+        if pronoun == "thou" && untouched && AUXILIARIES.contains(&prev.lower.as_str()) {
+            if let Some(verb) = self.conjugate_after(pronoun, &prev.lower, None) {
+                let verb = Casing::of(&prev.source).apply(&verb);
+                buf.replace_range(prev.out_start..prev_end, &verb);
+                current.out_start = buf.len();
+                return Some((pronoun, Some(verb.len())));
+            }
+        }
+        Some((pronoun, None))
     }
 
-    #[test]
-    fn word_works() {
-        let wrp = WordReplacement {
-            word: HashSet::from(["foo".to_string()]),
-            replacement: HashSet::from(["foobar".to_string()]),
-            ..Default::default()
-        };
-
-        assert!(matches!(
-            wrp.matches("", "foo"),
-            Some(MatchKind::Word("foo"))
-        ));
-        assert!(matches!(wrp.matches("foo", ""), None));
-        assert!(matches!(wrp.matches("foobar", ""), None));
-        assert!(matches!(wrp.matches("", "foobar"), None));
+    /// The word replacement rules, in the order they are tried.
+    fn rules(&self) -> &[WordReplacement] {
+        self.word_replacements
+            .get(RULES_KEY)
+            .map_or(&[], Vec::as_slice)
     }
 
-    #[test]
-    fn full_example() {
+    /// Translates the input string and optionally prepends/appends words,
+    /// drawing every random choice from `rng`.
+    ///
+    /// Each sentence is given an interjection before it and an exclamation
+    /// after it with the prepend and append probabilities, so longer texts
+    /// get more of them.
+    pub fn translate_postprocess_with_rng(
+        &self,
+        input: &str,
+        prepend: bool,
+        append: bool,
+        rng: &mut impl Rng,
+    ) -> String {
+        if !prepend && !append {
+            return self.translate_with_rng(input, rng);
+        }
+
+        let mut flourish_rng = flourish_rng(rng);
+        let translated = self.translate_with_rng(input, rng);
+        self.add_flourishes(translated, prepend, append, &mut flourish_rng)
+    }
+
+    /// Adds interjections and exclamations to the sentences of `translated`,
+    /// making the articles before them agree.
+    fn add_flourishes(
+        &self,
+        translated: String,
+        prepend: bool,
+        append: bool,
+        rng: &mut impl Rng,
+    ) -> String {
+        let edits = self.flourishes(&translated, prepend, append, rng);
+        // If nothing was added, return the translation as-is.
+        if edits.is_empty() {
+            return translated;
+        }
+        let output = apply_edits(&translated, &edits);
+        match article_fixes(&output, &edited_ranges(&edits)).as_slice() {
+            [] => output,
+            fixes => apply_edits(&output, fixes),
+        }
+    }
+
+    /// Translates everything read from `input` and writes it to `output`,
+    /// without holding more than a few words in memory at a time.
+    ///
+    /// The output is the same as that of `translate_with_rng` for the whole
+    /// input at once: the words which later ones may still be matched with
+    /// are carried over from one piece of the input to the next, and text is
+    /// only written once none of it can change, up to the whitespace after a
+    /// settled word.
+    pub fn translate_stream<R: BufRead, W: Write>(
+        &self,
+        input: R,
+        output: W,
+        rng: &mut impl Rng,
+    ) -> io::Result<()> {
+        self.translate_postprocess_stream(input, output, false, false, rng)
+    }
+
+    /// Like `translate_stream`, but also prepends and appends words to each
+    /// sentence like `translate_postprocess_with_rng`, with the same result
+    /// as it for the whole input. Text is then held and written a sentence at
+    /// a time.
+    pub fn translate_postprocess_stream<R: BufRead, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W,
+        prepend: bool,
+        append: bool,
+        rng: &mut impl Rng,
+    ) -> io::Result<()> {
+        let mut flourish_rng = (prepend || append).then(|| flourish_rng(rng));
+        let mut words = WordTranslator::new(self, 1024);
+        let mut spans = Vec::new();
+        // Input which has been read but not translated yet, and the bytes
+        // read after it which do not make up a whole character yet
+        let mut pending = String::new();
+        let mut partial = Vec::new();
+
+        loop {
+            let eof = read_chunk(&mut input, &mut partial, &mut pending)?;
+            let tokens = arp_tokenizer::tokenize(&pending);
+            let count = match eof {
+                true => tokens.len(),
+                false => held_back(&tokens),
+            };
+            words.translate(&tokens, count, rng, Some(&mut spans));
+            let translated: usize = tokens[..count].iter().map(|t| t.as_str().len()).sum();
+            pending.drain(..translated);
+
+            let split = match eof {
+                true => Some(words.buf.len()),
+                false => stream_split(&words.buf, words.settled(), flourish_rng.is_some()),
+            };
+            if let Some(split) = split {
+                let (text, spans) = words.take(split, &mut spans);
+                let mut text = agree_with_changes(text, &spans);
+                if let Some(rng) = flourish_rng.as_mut() {
+                    text = self.add_flourishes(text, prepend, append, rng);
+                }
+                output.write_all(text.as_bytes())?;
+            }
+            if eof {
+                break;
+            }
+        }
+        output.flush()
+    }
+
+    /// Translates the input string, drawing every random choice from `rng`.
+    pub fn translate_with_rng(&self, input: &str, rng: &mut impl Rng) -> String {
+        let mut spans = Vec::new();
+        let buf = self.translate_words(input, rng, Some(&mut spans));
+        agree_with_changes(buf, &spans)
+    }
+
+    /// Like `translate_with_rng`, but also returns the spans of the output
+    /// which differ from the input, and the rules and rolls behind them.
+    pub fn translate_explained_with_rng(&self, input: &str, rng: &mut impl Rng) -> Explanation {
+        let mut spans = Vec::new();
+        let output = self.translate_words(input, rng, Some(&mut spans));
+        let mut explanation = Explanation { output, spans };
+        let fixes = article_fixes(&explanation.output, &explanation.changed());
+        explanation.apply_all(fixes);
+        explanation.index_utf16(input);
+        explanation
+    }
+
+    /// Like `translate_postprocess_with_rng`, but also returns the spans of
+    /// the output which differ from the input, including the interjections
+    /// and exclamations added.
+    pub fn translate_postprocess_explained_with_rng(
+        &self,
+        input: &str,
+        prepend: bool,
+        append: bool,
+        rng: &mut impl Rng,
+    ) -> Explanation {
+        let mut flourish_rng = (prepend || append).then(|| flourish_rng(rng));
+        let mut explanation = self.translate_explained_with_rng(input, rng);
+        let Some(flourish_rng) = flourish_rng.as_mut() else {
+            return explanation;
+        };
+        let edits = self.flourishes(&explanation.output, prepend, append, flourish_rng);
+        if !edits.is_empty() {
+            explanation.apply_all(edits);
+            let fixes = article_fixes(&explanation.output, &explanation.changed());
+            explanation.apply_all(fixes);
+            explanation.index_utf16(input);
+        }
+        explanation
+    }
+
+    /// Picks a different translation for the segment at `index` of
+    /// `segments`, as returned by `translate_segments`, drawing every random
+    /// choice from `rng`.
+    ///
+    /// A segment from a rule gets another of its replacements, and one kept
+    /// after a failed roll gets the replacement of the first rule which
+    /// failed. Interjections and exclamations are chosen anew. The articles
+    /// before the segment are made to agree with it again. Other segments,
+    /// and those with a single possible translation, are left as they are.
+    pub fn reroll_segment_with_rng(
+        &self,
+        segments: &[Segment],
+        index: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<Segment> {
+        let mut segments = segments.to_vec();
+        let Some(current) = segments.get(index).map(|s| s.translated.as_str()) else {
+            return segments;
+        };
+
+        let rerolled = (0..REROLL_ATTEMPTS)
+            .filter_map(|_| self.reroll(&segments, index, rng))
+            .find(|(translated, _)| translated != current);
+        let Some((translated, origin)) = rerolled else {
+            return segments;
+        };
+        segments[index].set(translated, Some(origin));
+        segments[index].rolls.clear();
+        segments[index].template = None;
+
+        let output: String = segments.iter().map(|s| s.translated.as_str()).collect();
+        let start: usize = segments[..index].iter().map(|s| s.translated.len()).sum();
+        let changed = start..start + segments[index].translated.len();
+        for fix in article_fixes(&output, &[changed]).into_iter().rev() {
+            edit_segments(&mut segments, fix);
+        }
+        segments
+    }
+
+    /// Picks a translation for the segment at `index` of `segments`, if it
+    /// can have another.
+    fn reroll(
+        &self,
+        segments: &[Segment],
+        index: usize,
+        rng: &mut impl Rng,
+    ) -> Option<(String, Origin)> {
+        let segment = &segments[index];
+        let rule = match segment.origin? {
+            Origin::Rule { index, .. } => index,
+            Origin::Kept => segment.rolls.first()?.rule,
+            Origin::Interjection => {
+                let interjection = choose_weighted(&self.prepended_words, rng)?;
+                let interjection = self.expand_templates(interjection, &self.templates, rng);
+                return Some((interjection.into_owned(), Origin::Interjection));
+            }
+            Origin::Exclamation => {
+                let exclamation = choose_weighted(&self.appended_words, rng)?;
+                let exclamation = self.expand_templates(exclamation, &self.templates, rng);
+                return Some((format!(" {}", exclamation), Origin::Exclamation));
+            }
+            _ => return None,
+        };
+
+        // The words the rule matched, along with those before them since the
+        // last punctuation.
+        let original: String = segments[..=index]
+            .iter()
+            .map(|s| s.original.as_str())
+            .collect();
+        let words = match_words(&original);
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+
+        let n = self.rules().get(rule)?;
+        let kind = n.matches(&words)?;
+        let captures = MatchTemplates {
+            word: words.last().copied().unwrap_or_default(),
+            prev: words.len().checked_sub(2).map(|i| words[i]),
+            templates: &self.templates,
+        };
+        let replacement = n.replace(&kind, rng);
+        let replacement = self.expand_templates(&replacement, &captures, rng);
+        let first = segment
+            .original
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        Some((
+            Casing::of(first).apply(&replacement),
+            Origin::Rule { index: rule, kind },
+        ))
+    }
+
+    /// Translates the words of `input`, without making articles agree with
+    /// them. Every change made is added to `spans`, if given.
+    fn translate_words(
+        &self,
+        input: &str,
+        rng: &mut impl Rng,
+        spans: Option<&mut Vec<Span>>,
+    ) -> String {
+        let tokens = arp_tokenizer::tokenize(input);
+        let mut words = WordTranslator::new(self, input.len() * 2);
+        words.translate(&tokens, tokens.len(), rng, spans);
+        words.buf
+    }
+
+    /// The interjections and exclamations to add to the sentences of
+    /// `translated`, along with the capital letters they call for, in order.
+    ///
+    /// Each sentence is given an interjection before it and an exclamation
+    /// after it with the prepend and append probabilities, so longer texts
+    /// get more of them.
+    fn flourishes(
+        &self,
+        translated: &str,
+        prepend: bool,
+        append: bool,
+        rng: &mut impl Rng,
+    ) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        let mut offset = 0;
+
+        for sentence in split_sentences(translated) {
+            let start = offset;
+            offset += sentence.len();
+            let body = sentence.trim_end();
+            if body.is_empty() {
+                continue;
+            }
+
+            let interjection = match prepend {
+                true => self.prepend(rng),
+                false => Cow::default(),
+            };
+            let interjection = self.expand_templates(&interjection, &self.templates, rng);
+            if !interjection.is_empty() {
+                // After an exclamation such as "Hark! " the sentence starts anew.
+                let capital = match interjection.trim_end().ends_with(['.', '!', '?']) {
+                    true => body.char_indices().find(|(_, c)| c.is_alphabetic()),
+                    false => None,
+                };
+                edits.push(Edit {
+                    range: start..start,
+                    text: interjection.into_owned(),
+                    origin: Some(Origin::Interjection),
+                });
+                if let Some((i, c)) = capital.filter(|(_, c)| !c.is_uppercase()) {
+                    edits.push(Edit {
+                        range: start + i..start + i + c.len_utf8(),
+                        text: c.to_uppercase().collect(),
+                        origin: None,
+                    });
+                }
+            }
+
+            let exclamation = match append && body.ends_with(|c: char| c.is_ascii_punctuation()) {
+                true => self.append(rng),
+                false => Cow::default(),
+            };
+            if !exclamation.is_empty() {
+                let exclamation = self.expand_templates(&exclamation, &self.templates, rng);
+                edits.push(Edit {
+                    range: start + body.len()..start + body.len(),
+                    text: format!(" {}", exclamation),
+                    origin: Some(Origin::Exclamation),
+                });
+            }
+        }
+        edits
+    }
+}
+
+/// The lower case words at the end of `text` which a rule could match, up to
+/// the last punctuation, or that punctuation if `text` ends with it.
+fn match_words(text: &str) -> Vec<String> {
+    let tokens = arp_tokenizer::tokenize(text);
+    if let Some(ArpToken::Punct(punct)) = tokens.last() {
+        return vec![punct.to_string()];
+    }
+
+    let mut words: Vec<String> = tokens
+        .iter()
+        .rev()
+        .take_while(|token| !matches!(token, ArpToken::Punct(_)))
+        .filter_map(|token| match token {
+            ArpToken::Word(word) => Some(word.to_lowercase()),
+            _ => None,
+        })
+        .collect();
+    words.reverse();
+    words
+}
+
+/// The span for a rule matched by `match_on_nodes`, or for the text it left
+/// alone after the rolls in `rolls` failed.
+fn explain_match(
+    mr: Option<MatchResult>,
+    source: Range<usize>,
+    output: Range<usize>,
+    rolls: &[Roll],
+) -> Option<Span> {
+    let (origin, template) = match mr {
+        Some(mr) => (
+            Origin::Rule {
+                index: mr.index,
+                kind: mr.kind,
+            },
+            mr.template,
+        ),
+        None if !rolls.is_empty() => (Origin::Kept, None),
+        None => return None,
+    };
+    Some(Span {
+        source,
+        output,
+        origin,
+        rolls: rolls.to_vec(),
+        template,
+        source_utf16: None,
+        output_utf16: None,
+    })
+}
+
+/// Configures an `AutoRP` before use.
+///
+/// # Example
+/// ```
+/// use yeold::autorp::AutoRP;
+/// let autorp = AutoRP::builder()
+///     .prepend_probability(0.2)
+///     .append_probability(0.8)
+///     .build();
+/// assert_eq!(autorp.append_probability(), 0.8);
+/// ```
+#[derive(Debug)]
+pub struct AutoRPBuilder {
+    autorp: AutoRP,
+}
+
+impl From<AutoRP> for AutoRPBuilder {
+    fn from(autorp: AutoRP) -> Self {
+        Self { autorp }
+    }
+}
+
+impl AutoRPBuilder {
+    /// Merges extra rules over the current ones, see `AutoRP::merge`.
+    pub fn rules(mut self, rules: AutoRP) -> Self {
+        self.autorp.merge(rules);
+        self
+    }
+
+    /// Sets the probability that a word is prepended to each sentence when prepending is requested.
+    pub fn prepend_probability(mut self, probability: f64) -> Self {
+        self.autorp.set_prepend_probability(probability);
+        self
+    }
+
+    /// Sets the probability that a word is appended to each sentence when appending is requested.
+    pub fn append_probability(mut self, probability: f64) -> Self {
+        self.autorp.set_append_probability(probability);
+        self
+    }
+
+    /// Enables or disables translating "you" and "your" by their position in
+    /// the sentence.
+    pub fn pronoun_agreement(mut self, enabled: bool) -> Self {
+        self.autorp.set_pronoun_agreement(enabled);
+        self
+    }
+
+    /// Sets how many levels of templates within templates are expanded.
+    pub fn max_template_depth(mut self, depth: usize) -> Self {
+        self.autorp.max_template_depth = Some(depth);
+        self
+    }
+
+    /// Returns the configured `AutoRP`.
+    pub fn build(self) -> AutoRP {
+        self.autorp
+    }
+}
+
+/// Makes the articles of `text`, as translated with `spans`, agree with the
+/// replacements and the adjectives prepended to them, which may start with a
+/// different sound than the words they replaced.
+fn agree_with_changes(text: String, spans: &[Span]) -> String {
+    let changed: Vec<Range<usize>> = spans
+        .iter()
+        .filter(|span| span.origin != Origin::Kept)
+        .map(|span| span.output.clone())
+        .collect();
+    match article_fixes(&text, &changed).as_slice() {
+        [] => text,
+        fixes => apply_edits(&text, fixes),
+    }
+}
+
+/// A generator for the interjections and exclamations, seeded from `rng`
+/// before any word is translated. Drawing them apart from the words lets
+/// `translate_postprocess_stream` add them as it goes, yet pick the same
+/// ones as `translate_postprocess_with_rng` does for the whole text.
+fn flourish_rng(rng: &mut impl Rng) -> StdRng {
+    StdRng::seed_from_u64(rng.gen())
+}
+
+/// Reads what `input` has buffered, up to the end of the line, onto `pending`,
+/// keeping in `partial` the bytes of a character cut off by the end of the
+/// buffer. Returns `true` at the end of the input.
+fn read_chunk(
+    input: &mut impl BufRead,
+    partial: &mut Vec<u8>,
+    pending: &mut String,
+) -> io::Result<bool> {
+    let available = input.fill_buf()?;
+    let len = match available.iter().position(|&b| b == b'\n') {
+        Some(newline) => newline + 1,
+        None => available.len(),
+    };
+    partial.extend_from_slice(&available[..len]);
+    input.consume(len);
+
+    let valid = match std::str::from_utf8(partial) {
+        Ok(text) => text.len(),
+        Err(err) if err.error_len().is_none() && len > 0 => err.valid_up_to(),
+        Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+    };
+    let text = std::str::from_utf8(&partial[..valid]).expect("checked above");
+    pending.push_str(text);
+    partial.drain(..valid);
+    Ok(len == 0)
+}
+
+/// How many of `tokens`, the input read so far, can be translated before more
+/// is read. The text after the last whitespace may be cut off, even within a
+/// word such as "don't", and so waits along with a word followed only by
+/// whitespace, which waits for the word after it that pronouns and
+/// conjugations depend on.
+fn held_back(tokens: &[ArpToken]) -> usize {
+    let cut_off = tokens
+        .iter()
+        .rev()
+        .take_while(|t| !matches!(t, ArpToken::Ws(_)))
+        .count();
+    let tokens = &tokens[..tokens.len() - cut_off];
+    let trailing = tokens
+        .iter()
+        .rev()
+        .take_while(|t| matches!(t, ArpToken::Ws(_)))
+        .count();
+    let end = tokens.len() - trailing;
+    match end.checked_sub(1).map(|i| &tokens[i]) {
+        Some(ArpToken::Word(_)) => end - 1,
+        _ => tokens.len(),
+    }
+}
+
+/// Returns the last place within the first `settled` bytes of `text` where it
+/// can be split so that the text before it can be written out, if any.
+///
+/// The split is made after a run of whitespace, and so where a sentence, and
+/// the interjection before it, would start. With `by_sentence` it is only made
+/// between sentences, since exclamations depend on how a sentence ends. It is
+/// never made next to an "a" or "an", whose form depends on the words around
+/// it.
+fn stream_split(text: &str, settled: usize, by_sentence: bool) -> Option<usize> {
+    let is_article =
+        |word: Option<&str>| matches!(word.map(str::to_lowercase).as_deref(), Some("a" | "an"));
+    let fits = |split: usize| {
+        let before = text[..split].trim_end();
+        let after = &text[split..];
+        let fits = after.starts_with(|c: char| !c.is_whitespace())
+            && !is_article(before.rsplit(|c: char| !c.is_alphanumeric()).next())
+            && !is_article(after.split(|c: char| !c.is_alphanumeric()).next());
+        fits.then_some(split)
+    };
+
+    if by_sentence {
+        let mut starts: Vec<usize> = split_sentences(&text[..settled])
+            .iter()
+            .scan(0, |offset, sentence| {
+                *offset += sentence.len();
+                Some(*offset)
+            })
+            .collect();
+        starts.retain(|&start| start < settled);
+        return starts.into_iter().rev().find_map(fits);
+    }
+
+    text[..settled]
+        .char_indices()
+        .rev()
+        .filter(|&(i, c)| c.is_whitespace() && i > 0)
+        .find_map(|(i, c)| fits(i + c.len_utf8()))
+}
+
+/// Picks a key with probability proportional to its weight, or `None` if
+/// every weight is zero.
+fn choose_weighted<'a>(weights: &'a IndexMap<String, u32>, rng: &mut impl Rng) -> Option<&'a str> {
+    let total: u64 = weights.values().map(|&w| w as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0..total);
+    for (word, &weight) in weights {
+        if roll < weight as u64 {
+            return Some(word);
+        }
+        roll -= weight as u64;
+    }
+    unreachable!("the roll is always below the total weight")
+}
+
+/// Picks a random entry of a list of replacements, which rules are expected
+/// to have.
+fn choose<'a>(entries: &'a [String], rng: &mut impl Rng) -> &'a str {
+    entries.choose(rng).expect("a rule without replacements")
+}
+
+/// The different kinds of matches that can occur.
+/// * `Previous` is used to match the previous word and the current word.
+/// * `Word` is used to match the current word.
+/// * `Plural` is used to match the plural of the current word.
+/// * `Phrase` is used to match a sequence of words ending at the current word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// Previous word must match
+    Previous,
+    /// word match
+    Word,
+    /// plural word match
+    Plural,
+    /// phrase match, covering the given number of words
+    Phrase(usize),
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchKind::Previous => write!(f, "previous word"),
+            MatchKind::Word => write!(f, "word"),
+            MatchKind::Plural => write!(f, "plural"),
+            MatchKind::Phrase(len) => write!(f, "{} word phrase", len),
+        }
+    }
+}
+
+impl MatchKind {
+    /// The number of words covered by the match.
+    fn len(&self) -> usize {
+        match self {
+            MatchKind::Previous => 2,
+            MatchKind::Word | MatchKind::Plural => 1,
+            MatchKind::Phrase(len) => *len,
+        }
+    }
+
+    /// How specific the match is, higher being more specific: a phrase,
+    /// longer phrases first, then a previous word followed by a word, then a
+    /// word and last a plural.
+    fn specificity(&self) -> usize {
+        match self {
+            MatchKind::Plural => 0,
+            MatchKind::Word => 1,
+            MatchKind::Previous => 2,
+            MatchKind::Phrase(len) => 2 + len,
+        }
+    }
+}
+
+impl WordReplacement {
+    /// Returns the probability that this replacement is applied when it matches.
+    ///
+    /// `probability` wins if it is set, otherwise this is `1 / chance`.
+    pub fn probability(&self) -> f64 {
+        match (self.probability, self.chance) {
+            (Some(p), _) => p.clamp(0., 1.),
+            (None, 0) => 1.,
+            (None, n) => 1. / n as f64,
+        }
+    }
+
+    /// The order in which this rule is tried when it matches as `kind`,
+    /// lowest first: by priority, then by how specific the match is. Rules
+    /// which compare equal are tried in file order.
+    pub(crate) fn precedence(&self, kind: MatchKind) -> (Reverse<i32>, Reverse<usize>) {
+        (Reverse(self.priority), Reverse(kind.specificity()))
+    }
+
+    /// Rolls the dice for this replacement, returning `true` if it should be applied.
+    /// Certain replacements do not consume any randomness.
+    fn is_chance(&self, rng: &mut impl Rng) -> bool {
+        let probability = self.probability();
+        if probability >= 1. {
+            return true;
+        }
+        let rand: f64 = rng.sample(Standard);
+
+        rand < probability
+    }
+
+    /// Returns a `MatchKind` if the most recent words match the `WordReplacement`.
+    /// `words` holds the current word last, preceded by the words before it.
+    /// The words must be lower case, as the sets loaded from a rules file are.
+    pub(crate) fn matches(&self, words: &[&str]) -> Option<MatchKind> {
+        let next = *words.last()?;
+
+        if !self.phrase.is_empty() {
+            return self
+                .phrase
+                .iter()
+                .filter(|p| !p.is_empty() && p.len() <= words.len())
+                .filter(|p| {
+                    p.iter()
+                        .zip(&words[words.len() - p.len()..])
+                        .all(|(a, b)| a == b)
+                })
+                .map(|p| MatchKind::Phrase(p.len()))
+                .max_by_key(MatchKind::len);
+        }
+        if !self.prev.is_empty() {
+            let current = words.len().checked_sub(2).map(|i| words[i])?;
+            if self.prev.contains(current) && self.word.contains(next) {
+                return Some(MatchKind::Previous);
+            }
+            return None;
+        }
+        if self.word.contains(next) {
+            return Some(MatchKind::Word);
+        }
+        if self.word_plural.contains(next) {
+            return Some(MatchKind::Plural);
+        }
+        None
+    }
+
+    /// The most words this rule can match at once.
+    pub(crate) fn max_len(&self) -> usize {
+        match self.prev.is_empty() {
+            true => self.phrase.iter().map(Vec::len).max().unwrap_or(1),
+            false => 2,
+        }
+    }
+
+    /// Picks a replacement for an existing match, along with any prepended words.
+    fn replace(&self, kind: &MatchKind, rng: &mut impl Rng) -> String {
+        let replacement = match kind {
+            MatchKind::Previous | MatchKind::Word | MatchKind::Phrase(_) => {
+                choose(&self.replacement, rng)
+            }
+            MatchKind::Plural => choose(&self.replacement_plural, rng),
+        };
+
+        self.prepend(rng) + replacement
+    }
+
+    /// Prepends the replacement word with a random number of words from the `replacement_prepend` set.
+    /// The number of words is determined by the `prepend_count` field.
+    /// If `replacement_prepend` is empty, returns an empty string.
+    /// Otherwise, returns the joined string with a comma and space.
+    fn prepend(&self, rng: &mut impl Rng) -> String {
+        if self.replacement_prepend.is_empty() {
+            return String::default();
+        }
+        let mut res: Vec<&str> = Vec::with_capacity(self.prepend_count as usize);
+        for _ in 0..self.prepend_count {
+            res.push(choose(&self.replacement_prepend, rng));
+        }
+        res.join(", ").to_string() + " "
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::template_string::TemplateError;
+
+    #[test]
+    fn previous_works() {
+        let wrp = WordReplacement {
+            prev: IndexSet::from(["foo".to_string()]),
+            word: IndexSet::from(["bar".to_string()]),
+            replacement: vec!["foobar".to_string()],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            wrp.matches(&["foo", "bar"]),
+            Some(MatchKind::Previous)
+        ));
+        assert!(wrp.matches(&["", "bar"]).is_none());
+        assert!(wrp.matches(&["foo", ""]).is_none());
+    }
+
+    #[test]
+    fn word_works() {
+        let wrp = WordReplacement {
+            word: IndexSet::from(["foo".to_string()]),
+            replacement: vec!["foobar".to_string()],
+            ..Default::default()
+        };
+
+        assert!(matches!(wrp.matches(&["", "foo"]), Some(MatchKind::Word)));
+        assert!(wrp.matches(&["foo", ""]).is_none());
+        assert!(wrp.matches(&["foobar", ""]).is_none());
+        assert!(wrp.matches(&["", "foobar"]).is_none());
+    }
+
+    #[test]
+    fn full_example() {
         let autrp = AutoRP {
             word_replacements: {
-                let mut hm: HashMap<_, _> = HashMap::new();
+                let mut hm: IndexMap<_, _> = IndexMap::new();
                 let lists: Vec<WordReplacement> = vec![WordReplacement {
-                    word: HashSet::from(["foo".to_string()]),
-                    replacement: HashSet::from(["foobar".to_string()]),
+                    word: IndexSet::from(["foo".to_string()]),
+                    replacement: vec!["foobar".to_string()],
                     chance: 1,
                     ..Default::default()
                 }];
@@ -416,60 +1810,1406 @@ mod tests {
             ..Default::default()
         };
 
-        assert_eq!(autrp.translate("foo"), "foobar");
+        assert_eq!(autrp.translate("foo"), "foobar");
+    }
+
+    #[test]
+    fn punctuation_is_preserved() {
+        let autrp = AutoRP {
+            word_replacements: {
+                let mut hm: IndexMap<_, _> = IndexMap::new();
+                let lists: Vec<WordReplacement> = vec![
+                    WordReplacement {
+                        prev: IndexSet::from(["thank".to_string()]),
+                        word: IndexSet::from(["you".to_string()]),
+                        replacement: vec!["thankee".to_string()],
+                        chance: 1,
+                        ..Default::default()
+                    },
+                    WordReplacement {
+                        word: IndexSet::from(["friend".to_string()]),
+                        replacement: vec!["companion".to_string()],
+                        chance: 1,
+                        ..Default::default()
+                    },
+                ];
+                hm.insert("1".to_string(), lists);
+                hm
+            },
+            pronoun_agreement: Some(false),
+            ..Default::default()
+        };
+
+        assert_eq!(autrp.translate("thank you!"), "thankee!");
+        assert_eq!(autrp.translate("(friend)"), "(companion)");
+        assert_eq!(
+            autrp.translate("my friend, thank you?"),
+            "my companion, thankee?"
+        );
+        assert_eq!(autrp.translate("thank, you"), "thank, you");
+        assert_eq!(
+            autrp.translate("hello\u{a0}world friend"),
+            "hello\u{a0}world companion"
+        );
+        assert_eq!(autrp.translate("one\x0btwo friend"), "one\x0btwo companion");
+        assert_eq!(autrp.translate("thank\u{2003}you"), "thankee");
+    }
+
+    #[test]
+    fn case_is_preserved() {
+        let autrp = keyvalues_serde::from_str::<AutoRP>(
+            r#""autorp.txt"
+            {
+                "prepended_words" {}
+                "appended_words" {}
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "prev" "Thank"
+                        "word" "you"
+                        "replacement" "many good thanks to you"
+                    }
+                    "1"
+                    {
+                        "word" "HELLO"
+                        "replacement" "well met"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.translate("hello"), "well met");
+        assert_eq!(autrp.translate("Hello"), "Well met");
+        assert_eq!(autrp.translate("HELLO"), "WELL MET");
+        assert_eq!(autrp.translate("thank you"), "many good thanks to you");
+        assert_eq!(autrp.translate("Thank you"), "Many good thanks to you");
+        assert_eq!(autrp.translate("THANK YOU!"), "MANY GOOD THANKS TO YOU!");
+        assert_eq!(autrp.translate("I said hello"), "I said well met");
+    }
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let autrp = AutoRP::default();
+        let input = "Hello friend, thank you! I need gold and food.";
+
+        for seed in 0..20 {
+            assert_eq!(
+                autrp.translate_seeded(input, seed),
+                autrp.translate_seeded(input, seed)
+            );
+            assert_eq!(
+                autrp.translate_postprocess_seeded(input, true, true, seed),
+                autrp.translate_postprocess_seeded(input, true, true, seed)
+            );
+        }
+
+        let outputs: HashSet<String> = (0..20)
+            .map(|seed| autrp.translate_seeded(input, seed))
+            .collect();
+        assert!(outputs.len() > 1);
+
+        // Rules keep the order of the file, so a dictionary loaded again
+        // gives the same output, as does another run of the program.
+        let again = AutoRP::default();
+        for seed in 0..20 {
+            assert_eq!(
+                again.translate_postprocess_seeded(input, true, true, seed),
+                autrp.translate_postprocess_seeded(input, true, true, seed)
+            );
+        }
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "friend"
+                        "replacement" "companion"
+                        "replacement" "fellow"
+                        "replacement" "comrade"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let outputs: Vec<String> = (0..6)
+            .map(|seed| autrp.translate_seeded("friend", seed))
+            .collect();
+        assert_eq!(
+            outputs,
+            [
+                "comrade",
+                "comrade",
+                "companion",
+                "companion",
+                "comrade",
+                "companion"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_replacements_are_weights() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "fool"
+                        "replacement" "knave"
+                        "replacement_prepend" "puny"
+                        "replacement_prepend" "vile"
+                        "replacement_prepend" "puny"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            autrp.rules()[0].replacement_prepend,
+            vec!["puny", "vile", "puny"]
+        );
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let puny = (0..3_000)
+            .filter(|_| autrp.translate_with_rng("fool", &mut rng) == "puny knave")
+            .count();
+        assert!((1_850..2_150).contains(&puny));
+    }
+
+    #[test]
+    fn loading_reports_errors() {
+        assert!(AutoRP::from_str(AUTORP).is_ok());
+        assert!(AutoRP::from_reader(AUTORP.as_bytes()).is_ok());
+
+        assert!(matches!(
+            AutoRP::from_str("\"autorp.txt\"\n{\n\t\"word_replacements\"\n\t{\n\t\t\"1\" {"),
+            Err(AutoRPError::Syntax { line: 5, .. })
+        ));
+
+        assert!(matches!(
+            AutoRP::from_str(r#""autorp.txt" { "prepended_words" { "Hark! " 1 } }"#),
+            Err(AutoRPError::MissingSection("word_replacements"))
+        ));
+
+        assert!(matches!(
+            AutoRP::from_str(
+                r#""autorp.txt"
+                {
+                    "word_replacements"
+                    {
+                        "1" { "word" "hello" "replacement" "well met" }
+                        "1" { "word" "friend" "word_plural" "friends" "replacement" "chum" }
+                    }
+                }"#
+            ),
+            Err(AutoRPError::EmptyReplacements { rule: 1, word }) if word == "friends"
+        ));
+
+        assert!(matches!(
+            AutoRP::from_str(
+                r#""autorp.txt"
+                {
+                    "templates" { "oath" "by &god" "god" "&oath" }
+                    "word_replacements" { "1" { "word" "hello" "replacement" "well met" } }
+                }"#
+            ),
+            Err(AutoRPError::Template(TemplateError::Cycle(names))) if names.len() == 3
+        ));
+
+        assert!(matches!(
+            AutoRP::from_str(
+                r#""autorp.txt"
+                {
+                    "word_replacements" { "1" { "word" "wow" "replacement" "by &{god" } }
+                }"#
+            ),
+            Err(AutoRPError::Template(TemplateError::Syntax { .. }))
+        ));
+
+        assert!(matches!(
+            AutoRP::from_str(
+                r#""autorp.txt"
+                {
+                    "word_replacements" { "1" { "word" "hi" "replacement" "hello &{god|upper}" } }
+                }"#
+            ),
+            Err(AutoRPError::Template(TemplateError::Unknown { name, .. })) if name == "god"
+        ));
+    }
+
+    #[test]
+    fn merge_prefers_other() {
+        let mut autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "appended_words" { "Anon!" 1 }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "well met" }
+                    "1" { "word" "friend" "replacement" "chum" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let house = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "appended_words" { "Huzzah!" 1 }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "good morrow" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        autrp.merge(house);
+
+        assert_eq!(autrp.translate("hello friend"), "good morrow chum");
+        assert_eq!(autrp.rules().len(), 3);
+        assert_eq!(autrp.appended_words().count(), 2);
+    }
+
+    #[test]
+    fn partial_rules_are_validated_once_merged() {
+        let prepended = r#""autorp.txt" { "prepended_words" { "Hark! " 1 } }"#;
+        assert!(AutoRP::from_reader(prepended.as_bytes()).is_err());
+
+        let mut autrp = <AutoRP as Default>::default();
+        autrp.merge(AutoRP::from_reader_unvalidated(prepended.as_bytes()).unwrap());
+        assert!(matches!(
+            autrp.validate(),
+            Err(AutoRPError::MissingSection("word_replacements"))
+        ));
+
+        let mut autrp = AutoRP::default();
+        autrp.merge(AutoRP::from_reader_unvalidated(prepended.as_bytes()).unwrap());
+        assert!(autrp.validate().is_ok());
+        assert!(autrp.prepended_words().any(|(word, _)| word == "Hark! "));
+    }
+
+    #[test]
+    fn longest_phrase_wins() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1" { "word" "are" "replacement" "art" }
+                    "1" { "word" "you" "replacement" "thee" }
+                    "1" { "phrase" "are you" "replacement" "art thou" }
+                    "1" { "phrase" "how are you" "replacement" "how farest thou" }
+                    "1" { "phrase" "how are you doing" "replacement" "how goes it" }
+                    "1"
+                    {
+                        "phrase" "I don't know"
+                        "phrase" "I dunno"
+                        "replacement" "I know not"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.translate("how are you?"), "how farest thou?");
+        assert_eq!(autrp.translate("How  are you?"), "How farest thou?");
+        assert_eq!(autrp.translate("are you well?"), "art thou well?");
+        assert_eq!(autrp.translate("you are"), "thee art");
+        assert_eq!(autrp.translate("how, are you"), "how, art thou");
+        assert_eq!(autrp.translate("you are you"), "thee art thou");
+        assert_eq!(
+            autrp.translate("I dunno, I don't know!"),
+            "I know not, I know not!"
+        );
+        assert_eq!(autrp.translate("how are you doing"), "how goes it");
+        assert_eq!(autrp.rules()[5].phrase[0], vec!["i", "don't", "know"]);
+    }
+
+    #[test]
+    fn interjections_are_weighted() {
+        let mut autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepended_words"
+                {
+                    "Hark! " 9
+                    "There is much in what you say, and yet, " 1
+                }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "well met" }
+                }
+            }"#,
+        )
+        .unwrap();
+        autrp.set_prepended_weight("Zounds, ", 0);
+
+        let mut weights: Vec<_> = autrp.prepended_words().collect();
+        weights.sort();
+        assert_eq!(
+            weights,
+            vec![
+                ("Hark! ", 9),
+                ("There is much in what you say, and yet, ", 1),
+                ("Zounds, ", 0)
+            ]
+        );
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut counts: HashMap<&str, u32> = HashMap::new();
+        for _ in 0..10_000 {
+            *counts
+                .entry(choose_weighted(&autrp.prepended_words, &mut rng).unwrap())
+                .or_default() += 1;
+        }
+        assert!((8_800..9_200).contains(&counts["Hark! "]));
+        assert!(!counts.contains_key("Zounds, "));
+
+        autrp.set_prepended_weight("Hark! ", 0);
+        autrp.set_prepended_weight("There is much in what you say, and yet, ", 0);
+        assert_eq!(choose_weighted(&autrp.prepended_words, &mut rng), None);
+    }
+
+    #[test]
+    fn flourish_probability_is_configurable() {
+        let rules = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepend_probability" "0.25"
+                "prepended_words" { "Hark! " 1 }
+                "appended_words" { "Anon!" 1 }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "well met" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(rules.prepend_probability(), 0.25);
+        assert_eq!(rules.append_probability(), DEFAULT_FLOURISH_PROBABILITY);
+
+        let autrp = AutoRPBuilder::from(<AutoRP as Default>::default())
+            .rules(rules)
+            .append_probability(0.)
+            .build();
+        assert_eq!(autrp.prepend_probability(), 0.25);
+
+        let mut rng = StdRng::seed_from_u64(10);
+        let mut prepended = 0;
+        for _ in 0..10_000 {
+            let out = autrp.translate_postprocess_with_rng("hello!", true, true, &mut rng);
+            assert!(!out.ends_with("Anon!"));
+            if out.starts_with("Hark! ") {
+                prepended += 1;
+            }
+        }
+        assert!((2_300..2_700).contains(&prepended));
+    }
+
+    #[test]
+    fn templates_are_separate_from_words() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepend_probability" "1"
+                "prepended_words" { "By &god, " 1 }
+                "templates"
+                {
+                    "god" "Odin"
+                    "god" "Zeus"
+                }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "well met" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.templates["god"], vec!["Odin", "Zeus"]);
+        assert_eq!(autrp.translate("god godadj"), "god godadj");
+
+        let out = autrp.translate_postprocess("hello", true, false);
+        assert!(out == "By Odin, well met" || out == "By Zeus, well met");
+
+        // The built-in rules translate the words by picking from the
+        // templates of the same name.
+        let default = AutoRP::default();
+        for name in ["god", "godadj", "bodypart", "bodyadj"] {
+            let values = &default.templates[name];
+            assert!(!values.is_empty());
+            assert!(values.contains(&default.translate(name)));
+        }
+    }
+
+    #[test]
+    fn oh_my_god_is_translated() {
+        let autrp = AutoRP::default();
+        let gods = &autrp.templates["god"];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let out = autrp.translate_with_rng("oh my god", &mut rng);
+            assert!(
+                gods.iter().any(|god| out.ends_with(&format!(" {}", god))),
+                "{}",
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn nested_templates_expand_in_translate() {
+        let rules = r#""autorp.txt"
+            {
+                "templates"
+                {
+                    "oath" "by &god's &bodypart"
+                    "god" "Odin"
+                    "bodypart" "&bodyadj beard"
+                    "bodyadj" "mighty"
+                }
+                "word_replacements"
+                {
+                    "1" { "word" "wow" "replacement" "&oath" }
+                }
+            }"#;
+        let autrp = AutoRP::from_str(rules).unwrap();
+
+        assert_eq!(autrp.max_template_depth(), 8);
+        assert_eq!(autrp.translate("Wow!"), "By Odin's mighty beard!");
+        // Placeholders typed by the user are not expanded.
+        assert_eq!(autrp.translate_postprocess("&god", false, false), "&god");
+
+        let shallow = AutoRP::builder()
+            .rules(AutoRP::from_str(rules).unwrap())
+            .max_template_depth(2)
+            .build();
+        assert!(matches!(
+            shallow.validate(),
+            Err(AutoRPError::Template(TemplateError::TooDeep {
+                limit: 2,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn articles_agree_with_replacements() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1" { "word" "fool" "replacement" "idiot" }
+                    "1" { "word" "hour" "replacement" "while" }
+                    "1"
+                    {
+                        "word" "man"
+                        "replacement" "knave"
+                        "replacement_prepend" "ugly"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.translate("A fool!"), "An idiot!");
+        assert_eq!(autrp.translate("an hour"), "a while");
+        assert_eq!(autrp.translate("a man"), "an ugly knave");
+        // Articles before words which were not replaced are left alone.
+        assert_eq!(autrp.translate("a apple"), "a apple");
+        for text in [
+            "an SMG",
+            "an FBI agent",
+            "an MP3 player",
+            "Vitamin A is good",
+        ] {
+            assert_eq!(autrp.translate(text), text);
+        }
+        assert_eq!(
+            autrp.translate("a fool and a apple"),
+            "an idiot and a apple"
+        );
+    }
+
+    #[test]
+    fn replacements_capture_words() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "templates" { "prev" "unused" }
+                "word_replacements"
+                {
+                    "1" { "word" "you" "replacement" "thee &0" }
+                    "1" { "prev" "he" "word" "walks" "replacement" "&prev &{0|upper}!" }
+                    "1" { "word" "goes" "replacement" "&{prev}eth" }
+                    "1" { "word" "&" "replacement" "&0 &0" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.translate("You"), "Thee you");
+        assert_eq!(autrp.translate("He walks"), "He WALKS!");
+        assert_eq!(autrp.translate("goes, so go goes"), "eth, so go goeth");
+        assert_eq!(autrp.translate("R & D"), "R & & D");
+    }
+
+    #[test]
+    fn verbs_are_conjugated() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "conjugations"
+                {
+                    "1"
+                    {
+                        "subject" "he"
+                        "ending" "s"
+                        "suffix" "eth"
+                        "object" "it"
+                        "irregular" { "Has" "hath" }
+                    }
+                    "1"
+                    {
+                        "subject" "thou"
+                        "suffix" "est"
+                        "except" "too"
+                        "verb" "run"
+                        "object" "the"
+                    }
+                }
+                "word_replacements"
+                {
+                    "1" { "word" "you" "replacement" "thou" }
+                    "1" { "word" "runs" "replacement" "flees" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            autrp.translate("He MAKES it, he has it"),
+            "He MAKETH it, he hath it"
+        );
+        assert_eq!(
+            autrp.translate("You run. You too"),
+            "Thou runnest. Thou too"
+        );
+        // Words matched by a replacement are left to it.
+        assert_eq!(autrp.translate("he runs"), "he flees");
+        // Conjugation needs the subject right before the verb.
+        assert_eq!(autrp.translate("he, makes"), "he, makes");
+        // Only verbs are conjugated: listed ones, and those before an object.
+        assert_eq!(
+            autrp.translate("you mean the world"),
+            "thou meanest the world"
+        );
+        for text in [
+            "Thou my friend are great",
+            "thou in?",
+            "Thou moron!",
+            "thou very much",
+            "He plus she.",
+        ] {
+            assert_eq!(autrp.translate(text), text);
+        }
+    }
+
+    #[test]
+    fn pronouns_agree() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "conjugations"
+                {
+                    "1"
+                    {
+                        "subject" "thou"
+                        "suffix" "est"
+                        "except" "sure"
+                        "verb" "know"
+                        "verb" "see"
+                        "verb" "eat"
+                        "object" "that"
+                        "irregular"
+                        {
+                            "are" "art"
+                            "do" "dost"
+                            "did" "didst"
+                            "have" "hast"
+                            "does" "dost"
+                        }
+                    }
+                }
+                "word_replacements"
+                {
+                    "1" { "prev" "thank" "word" "you" "replacement" "thankee" }
+                    "1" { "word" "does" "replacement" "doeseth" }
+                    "1" { "word" "hello" "replacement" "hello there" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            autrp.translate("You know I love you."),
+            "Thou knowest I love thee."
+        );
+        assert_eq!(
+            autrp.translate("Are you sure? Do you?"),
+            "Art thou sure? Dost thou?"
+        );
+        // Once the auxiliary agrees, the verb after the subject does not.
+        assert_eq!(
+            autrp.translate("Did you see that? Do you know"),
+            "Didst thou see that? Dost thou know"
+        );
+        assert_eq!(autrp.translate("Have you eaten"), "Hast thou eaten");
+        assert_eq!(
+            autrp.translate("You are. You all are"),
+            "Thou art. Ye all are"
+        );
+        assert_eq!(
+            autrp.translate("your eye, your hand"),
+            "thine eye, thy hand"
+        );
+        assert_eq!(autrp.translate("Thank you"), "Thankee");
+
+        // A word replaced by a rule is not conjugated again, even when the
+        // replacement starts with it.
+        for (text, expected) in [
+            ("does you know", "doeseth thou know"),
+            ("hello you", "hello there thee"),
+        ] {
+            let explanation = autrp.translate_explained(text);
+            assert_eq!(explanation.output, expected);
+            assert!(explanation
+                .spans
+                .windows(2)
+                .all(|w| w[0].source.end <= w[1].source.start));
+        }
+
+        let off = AutoRPBuilder::from(<AutoRP as Default>::default())
+            .pronoun_agreement(false)
+            .build();
+        assert_eq!(off.translate("You know your place"), "You know your place");
+    }
+
+    #[test]
+    fn flourishes_are_added_per_sentence() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepend_probability" "1"
+                "append_probability" "1"
+                "prepended_words" { "Hark! " 1 }
+                "appended_words" { "Anon!" 1 }
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "well met" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            autrp.translate_postprocess("hello there. the end?\nno", true, true),
+            "Hark! Well met there. Anon! Hark! The end? Anon!\nHark! No"
+        );
+        assert_eq!(
+            autrp.translate_postprocess("hello there. the end", true, false),
+            "Hark! Well met there. Hark! The end"
+        );
+        assert_eq!(autrp.translate_postprocess("  ", true, true), "  ");
+    }
+
+    #[test]
+    fn streaming_matches_whole_text() {
+        let autrp = AutoRP::default();
+        let text = "Thank\nyou very\nmuch, my friend! How\nare you?\n\nI don't\nknow... Hello.";
+
+        for capacity in [1, 7, 64] {
+            let mut out = Vec::new();
+            let input = io::BufReader::with_capacity(capacity, text.as_bytes());
+            autrp
+                .translate_stream(input, &mut out, &mut StdRng::seed_from_u64(5))
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                autrp.translate_seeded(text, 5)
+            );
+        }
+
+        // Chat logs rarely end their lines with punctuation, and words may
+        // still be matched, or pick their form, across lines.
+        let text = "hey you\n  know what\nthank\nyou my friend\nhe is a\nman and a\nidiot\n\n\
+                    Vitamin\nA is good\r\ndid you\nsee that guy lol\n   \nbye";
+        for seed in 0..20 {
+            let mut out = Vec::new();
+            autrp
+                .translate_postprocess_stream(
+                    text.as_bytes(),
+                    &mut out,
+                    true,
+                    true,
+                    &mut StdRng::seed_from_u64(seed),
+                )
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                autrp.translate_postprocess_seeded(text, true, true, seed)
+            );
+        }
+
+        // A long line is written a sentence, or a word, at a time, and may
+        // be cut off by the reader anywhere, even within a character.
+        let text = "Hey you. Are you a idiot, or an man?! Thank you my friend, café \
+                    — I don't know. Mr. Smith is a Vitamin A fan "
+            .repeat(20);
+        for (capacity, seed) in [(1, 0), (3, 1), (16, 2), (64, 3)] {
+            for flourish in [false, true] {
+                let mut out = Vec::new();
+                let input = io::BufReader::with_capacity(capacity, text.as_bytes());
+                autrp
+                    .translate_postprocess_stream(
+                        input,
+                        &mut out,
+                        flourish,
+                        flourish,
+                        &mut StdRng::seed_from_u64(seed),
+                    )
+                    .unwrap();
+                assert_eq!(
+                    String::from_utf8(out).unwrap(),
+                    autrp.translate_postprocess_seeded(&text, flourish, flourish, seed)
+                );
+            }
+        }
+
+        let mut out = Vec::new();
+        autrp
+            .translate_postprocess_stream(&b""[..], &mut out, true, true, &mut thread_rng())
+            .unwrap();
+        assert!(out.is_empty());
+
+        let mut out = Vec::new();
+        let invalid = autrp.translate_stream(&b"caf\xc3"[..], &mut out, &mut thread_rng());
+        assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Output shared with the `Recording` reader of the input.
+    #[derive(Default, Clone)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads `text`, noting how much had been written to `output` once all
+    /// of it was read.
+    struct Recording<'a> {
+        text: &'a [u8],
+        output: Shared,
+        written_at_end: Option<usize>,
+    }
+
+    impl Read for Recording<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.text.is_empty() {
+                self.written_at_end
+                    .get_or_insert(self.output.0.borrow().len());
+            }
+            self.text.read(buf)
+        }
+    }
+
+    #[test]
+    fn streaming_writes_long_lines_as_it_goes() {
+        let autrp = AutoRP::default();
+        let text = "Hello there, my good friend. ".repeat(10_000);
+
+        for flourish in [false, true] {
+            let output = Shared::default();
+            let mut input = Recording {
+                text: text.as_bytes(),
+                output: output.clone(),
+                written_at_end: None,
+            };
+            autrp
+                .translate_postprocess_stream(
+                    io::BufReader::with_capacity(64, &mut input),
+                    output.clone(),
+                    flourish,
+                    flourish,
+                    &mut thread_rng(),
+                )
+                .unwrap();
+
+            let written_at_end = input.written_at_end.unwrap();
+            assert!(written_at_end > text.len() / 2, "{}", written_at_end);
+            assert!(written_at_end < output.0.borrow().len());
+        }
+    }
+
+    #[test]
+    fn stream_splits_between_settled_sentences() {
+        let all = |text: &str| stream_split(text, text.len(), true);
+        assert_eq!(all("one\ntwo three"), Some(4));
+        assert_eq!(all("one\ntwo\n  three"), Some(10));
+        assert_eq!(stream_split("one\ntwo\n  three", 9, true), Some(4));
+        assert_eq!(all("one\ntwo\n"), Some(4));
+        assert_eq!(all("one two"), None);
+        assert_eq!(all("one\n\n"), None);
+        assert_eq!(all("one a\ntwo"), None);
+        assert_eq!(all("x\none An\ntwo"), Some(2));
+        assert_eq!(all("One. Two! Three"), Some(10));
+        assert_eq!(all("Ask Mr. Smith"), None);
+        assert_eq!(all("One. An idiot"), None);
+    }
+
+    #[test]
+    fn stream_splits_after_settled_words() {
+        let all = |text: &str| stream_split(text, text.len(), false);
+        assert_eq!(all("one two  three"), Some(9));
+        assert_eq!(stream_split("one two  three", 8, false), Some(4));
+        assert_eq!(all("one two "), Some(4));
+        assert_eq!(all("one a two"), None);
+        assert_eq!(all("one two An"), Some(4));
+        assert_eq!(all(" one"), None);
+    }
+
+    #[test]
+    fn stream_holds_back_the_last_word() {
+        let tokens = arp_tokenizer::tokenize("thank you\n");
+        assert_eq!(held_back(&tokens), 2);
+        let tokens = arp_tokenizer::tokenize("thank you!\n");
+        assert_eq!(held_back(&tokens), tokens.len());
+        // The text after the last whitespace may be cut off, and the word
+        // before it waits for it.
+        let tokens = arp_tokenizer::tokenize("thank yo");
+        assert_eq!(held_back(&tokens), 0);
+        let tokens = arp_tokenizer::tokenize("thank you! don'");
+        assert_eq!(held_back(&tokens), 5);
+        assert_eq!(held_back(&[]), 0);
+    }
+
+    #[test]
+    fn explanations_index_javascript_strings() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepended_words" { "Hark! " "1" }
+                "prepend_probability" "1"
+                "word_replacements" { "1" { "word" "you" "replacement" "thee" } }
+            }"#,
+        )
+        .unwrap();
+
+        let explanation = autrp.translate_explained("café you");
+        let span = &explanation.spans[0];
+        assert_eq!((span.source.clone(), span.output.clone()), (6..9, 6..10));
+        assert_eq!((span.source_start(), span.source_end()), (5, 8));
+        assert_eq!((span.output_start(), span.output_end()), (5, 9));
+
+        let explanation = autrp.translate_postprocess_explained("café you", true, false);
+        assert_eq!(explanation.output, "Hark! Café thee");
+        let span = &explanation.spans[1];
+        assert_eq!((span.source_start(), span.source_end()), (5, 8));
+        assert_eq!((span.output_start(), span.output_end()), (11, 15));
+    }
+
+    #[test]
+    fn translations_are_explained() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "prepended_words" { "Hark! " "1" }
+                "prepend_probability" "1"
+                "templates" { "god" "Odin" }
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "man"
+                        "replacement" "knave"
+                        "replacement_prepend" "ugly"
+                    }
+                    "1" { "phrase" "thank you" "replacement" "gramercy" }
+                    "1" { "word" "foo" "replacement" "&god" }
+                    "1" { "word" "bar" "replacement" "baz" "probability" "0" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let input = "Thank you, a man! foo bar";
+
+        let explanation = autrp.translate_explained_seeded(input, 3);
+        assert_eq!(explanation.output, autrp.translate_seeded(input, 3));
+        assert_eq!(explanation.output, "Gramercy, an ugly knave! Odin bar");
+
+        let explained: Vec<_> = explanation
+            .spans
+            .iter()
+            .map(|span| {
+                (
+                    &input[span.source.clone()],
+                    &explanation.output[span.output.clone()],
+                    span.origin,
+                )
+            })
+            .collect();
+        assert_eq!(
+            explained,
+            vec![
+                (
+                    "Thank you",
+                    "Gramercy",
+                    Origin::Rule {
+                        index: 1,
+                        kind: MatchKind::Phrase(2)
+                    }
+                ),
+                ("a", "an", Origin::Article),
+                (
+                    "man",
+                    "ugly knave",
+                    Origin::Rule {
+                        index: 0,
+                        kind: MatchKind::Word
+                    }
+                ),
+                (
+                    "foo",
+                    "Odin",
+                    Origin::Rule {
+                        index: 2,
+                        kind: MatchKind::Word
+                    }
+                ),
+                ("bar", "bar", Origin::Kept),
+            ]
+        );
+        assert_eq!(explanation.spans[3].template.as_deref(), Some("&god"));
+        assert_eq!(
+            explanation.spans[4].rolls,
+            vec![Roll {
+                rule: 3,
+                probability: 0.,
+                passed: false
+            }]
+        );
+        assert_eq!(
+            explanation.spans[4].to_string(),
+            "kept; rolled 0.00 for rule 3: failed"
+        );
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let explanation =
+            autrp.translate_postprocess_explained_with_rng(input, true, false, &mut rng);
+        assert_eq!(
+            explanation.output,
+            autrp.translate_postprocess_seeded(input, true, false, 5)
+        );
+        assert_eq!(
+            explanation.output,
+            "Hark! Gramercy, an ugly knave! Hark! Odin bar"
+        );
+        let flourishes: Vec<_> = explanation
+            .spans
+            .iter()
+            .filter(|span| span.origin == Origin::Interjection)
+            .map(|span| (span.source.clone(), span.output.clone()))
+            .collect();
+        assert_eq!(flourishes, vec![(0..0, 0..6), (18..18, 31..37)]);
+        assert_eq!(&explanation.output[37..41], "Odin");
+        assert_eq!(explanation.spans.last().unwrap().output, 42..45);
+    }
+
+    #[test]
+    fn segments_cover_the_input() {
+        let autrp = AutoRP::default();
+        let words = [
+            "does", "YOU", "did", "you", "hello", "Hello", "are", "a", "an", "idiot", "thank",
+            "my", "friend", "know", "see", "café", "—", "god", "don't", "!", "?", ".", "\n", "  ",
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..300 {
+            let input: Vec<&str> = (0..rng.gen_range(1..16))
+                .map(|_| words[rng.gen_range(0..words.len())])
+                .collect();
+            let input = input.join(" ");
+            let flourish = rng.gen_bool(0.5);
+
+            let segments = autrp.translate_segments(&input, flourish, flourish);
+            let original: String = segments.iter().map(|s| s.original.as_str()).collect();
+            assert_eq!(original, input);
+
+            let rerolled = autrp.reroll_segment(segments, rng.gen_range(0..4));
+            let original: String = rerolled.iter().map(|s| s.original.as_str()).collect();
+            assert_eq!(original, input);
+        }
+    }
+
+    #[test]
+    fn segments_can_be_rerolled() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "man"
+                        "replacement" "knave"
+                        "replacement" "idiot"
+                    }
+                    "1" { "word" "bar" "replacement" "baz" "probability" "0" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let input = "a man, bar";
+        let joined = |segments: &[Segment]| -> (String, String) {
+            segments
+                .iter()
+                .map(|s| (s.original.as_str(), s.translated.as_str()))
+                .unzip()
+        };
+
+        let explanation = autrp.translate_explained_seeded(input, 1);
+        let segments = explanation.segments(input);
+        assert_eq!(
+            joined(&segments),
+            (input.into(), explanation.output.clone())
+        );
+        let man = segments.iter().position(|s| s.original == "man").unwrap();
+        assert_eq!(segments[man].rule_id, Some(0));
+        assert!(segments[man].changed);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let rerolled = autrp.reroll_segment_with_rng(&segments, man, &mut rng);
+        let expected = match explanation.output.as_str() {
+            "a knave, bar" => "an idiot, bar",
+            _ => "a knave, bar",
+        };
+        assert_eq!(joined(&rerolled), (input.into(), expected.into()));
+
+        // A word kept after a failed roll is given the rule's replacement.
+        let bar = rerolled.iter().position(|s| s.original == "bar").unwrap();
+        assert!(!rerolled[bar].changed && rerolled[bar].can_reroll());
+        let rerolled = autrp.reroll_segment_with_rng(&rerolled, bar, &mut rng);
+        assert!(joined(&rerolled).1.ends_with(", baz"));
+        assert_eq!(rerolled[bar].rule_id, Some(1));
+        assert!(rerolled[bar].changed);
+
+        // Text left alone has nothing else to be.
+        let comma = rerolled.iter().position(|s| s.original == ", ").unwrap();
+        assert!(!rerolled[comma].can_reroll());
+        assert_eq!(
+            autrp.reroll_segment_with_rng(&rerolled, comma, &mut rng),
+            rerolled
+        );
+    }
+
+    #[test]
+    fn alternatives_are_listed() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "friend"
+                        "word_plural" "friends"
+                        "replacement" "companion"
+                        "replacement" "fellow"
+                        "replacement_plural" "companions"
+                    }
+                    "1" { "phrase" "my friends" "replacement" "mine allies" }
+                    "1" { "word" "!" "replacement" "!!" "chance" "4" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let alternatives = autrp.alternatives("Hi my Friends!");
+        let listed: Vec<_> = alternatives
+            .iter()
+            .map(|a| {
+                let rules: Vec<_> = a
+                    .rules
+                    .iter()
+                    .map(|r| {
+                        let mut replacements = r.replacements.clone();
+                        replacements.sort();
+                        (r.rule, r.kind, r.source.clone(), replacements)
+                    })
+                    .collect();
+                (a.source.clone(), rules)
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (
+                    6..13,
+                    vec![
+                        (
+                            1,
+                            MatchKind::Phrase(2),
+                            3..13,
+                            vec!["mine allies".to_string()]
+                        ),
+                        (0, MatchKind::Plural, 6..13, vec!["companions".to_string()]),
+                    ]
+                ),
+                (
+                    13..14,
+                    vec![(2, MatchKind::Word, 13..14, vec!["!!".to_string()])]
+                ),
+            ]
+        );
+        assert_eq!(alternatives[1].rules[0].probability, 0.25);
+
+        // JavaScript gets offsets in UTF-16 code units.
+        let alternatives = autrp.alternatives("Café, my friend");
+        assert_eq!(alternatives[0].source, 10..16);
+        let offsets = |a: &Alternatives| (a.source_start(), a.source_end());
+        assert_eq!(offsets(&alternatives[0]), (9, 15));
+        assert_eq!(alternatives[0].rules[0].source_start(), 9);
+        assert!(autrp.alternatives("no rules here").is_empty());
+        assert!(<AutoRP as Default>::default().alternatives("hi").is_empty());
+    }
+
+    #[test]
+    fn rules_are_tried_by_priority_and_specificity() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1" { "word" "you" "replacement" "thee" }
+                    "1" { "prev" "thank" "word" "you" "replacement" "thankee" }
+                    "1" { "phrase" "thank you" "replacement" "many thanks" }
+                    "1" { "word" "friend" "replacement" "companion" }
+                    "1" { "word" "friend" "replacement" "ally" "priority" "1" }
+                    "1" { "phrase" "good sir" "replacement" "good sire" }
+                    "1" { "word" "sir" "replacement" "milord" "priority" "2" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(autrp.translate("thank you"), "many thanks");
+        assert_eq!(autrp.translate("you"), "thee");
+        assert_eq!(autrp.translate("friend"), "ally");
+        assert_eq!(autrp.translate("good sir"), "good milord");
+
+        let order: Vec<usize> = autrp.alternatives("thank you")[0]
+            .rules
+            .iter()
+            .map(|a| a.rule)
+            .collect();
+        assert_eq!(order, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn shadowed_rules_are_reported() {
+        let autrp = AutoRP::from_str(
+            r#""autorp.txt"
+            {
+                "word_replacements"
+                {
+                    "1" { "word" "hello" "replacement" "hail" }
+                    "1" { "word" "hello" "replacement" "greetings" }
+                    "1" { "word" "hi" "replacement" "hail" "chance" "2" }
+                    "1" { "word" "hi" "replacement" "ho" }
+                    "1" { "prev" "good" "word" "day" "replacement" "good morrow" }
+                    "1" { "phrase" "good day" "replacement" "well met" }
+                    "1" { "word" "sir" "replacement" "sire" }
+                    "1" { "word" "sir" "word" "madam" "replacement" "milord" }
+                    "1"
+                    {
+                        "word" "friend"
+                        "replacement" "companion"
+                        "priority" "-1"
+                    }
+                    "1" { "word" "friend" "word" "pal" "replacement" "ally" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let shadowed = autrp.shadowed_rules();
+        assert_eq!(
+            shadowed,
+            vec![
+                Shadowed {
+                    rule: 1,
+                    words: "hello".to_string(),
+                    by: vec![0],
+                },
+                Shadowed {
+                    rule: 4,
+                    words: "good day".to_string(),
+                    by: vec![5],
+                },
+                Shadowed {
+                    rule: 8,
+                    words: "friend".to_string(),
+                    by: vec![9],
+                },
+            ]
+        );
+        assert_eq!(
+            shadowed[1].to_string(),
+            "rule 4 (\"good day\") is never applied, as rule 5 always matches first"
+        );
+        assert!(AutoRP::default().shadowed_rules().is_empty());
     }
 
     #[test]
     fn plural_works() {
         let wrp = WordReplacement {
-            word_plural: HashSet::from(["foos".to_string()]),
-            replacement_plural: HashSet::from(["foobars".to_string()]),
+            word_plural: IndexSet::from(["foos".to_string()]),
+            replacement_plural: vec!["foobars".to_string()],
             ..Default::default()
         };
 
         assert!(matches!(
-            wrp.matches("", "foos"),
-            Some(MatchKind::Plural("foos"))
+            wrp.matches(&["", "foos"]),
+            Some(MatchKind::Plural)
         ));
-        assert!(matches!(wrp.matches("foo", ""), None));
-        assert!(matches!(wrp.matches("foobar", ""), None));
-        assert!(matches!(wrp.matches("", "foobar"), None));
+        assert!(wrp.matches(&["foo", ""]).is_none());
+        assert!(wrp.matches(&["foobar", ""]).is_none());
+        assert!(wrp.matches(&["", "foobar"]).is_none());
     }
 
     #[test]
     fn prepend_works() {
         let wrp = WordReplacement {
-            word: HashSet::from(["foo".to_string()]),
-            replacement: HashSet::from(["bar".to_string()]),
-            replacement_prepend: HashSet::from(["baz".to_string()]),
+            word: IndexSet::from(["foo".to_string()]),
+            replacement: vec!["bar".to_string()],
+            replacement_prepend: vec!["baz".to_string()],
             prepend_count: 1,
             ..Default::default()
         };
 
         let mut rng = thread_rng();
-        let result = wrp.translate("", "foo", &mut rng);
-        assert!(result.is_some());
-        let (_, replacement) = result.unwrap();
+        let kind = wrp.matches(&["", "foo"]);
+        assert!(kind.is_some());
+        let replacement = wrp.replace(&kind.unwrap(), &mut rng);
         assert_eq!(replacement, "baz bar");
     }
 
+    /// Counts how many times out of `trials` the replacement fires.
+    fn fired(wrp: &WordReplacement, trials: u32, seed: u64) -> u32 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..trials).filter(|_| wrp.is_chance(&mut rng)).count() as u32
+    }
+
     #[test]
     fn chance_works() {
-        let wrp = WordReplacement {
-            word: HashSet::from(["foo".to_string()]),
-            replacement: HashSet::from(["bar".to_string()]),
+        let mut wrp = WordReplacement {
+            word: IndexSet::from(["foo".to_string()]),
+            replacement: vec!["bar".to_string()],
             chance: 2,
             ..Default::default()
         };
 
-        let mut rng = thread_rng();
-        let mut success = false;
-        for _ in 0..100 {
-            if wrp.is_chance(&mut rng) {
-                success = true;
-                break;
-            }
-        }
-        assert!(success);
+        assert!((4_800..5_200).contains(&fired(&wrp, 10_000, 1)));
+
+        wrp.chance = 4;
+        assert!((2_300..2_700).contains(&fired(&wrp, 10_000, 2)));
+
+        wrp.chance = 1;
+        assert_eq!(fired(&wrp, 1_000, 3), 1_000);
+
+        wrp.chance = 0;
+        assert_eq!(fired(&wrp, 1_000, 4), 1_000);
+    }
+
+    #[test]
+    fn probability_works() {
+        let mut wrp = WordReplacement {
+            word: IndexSet::from(["foo".to_string()]),
+            replacement: vec!["bar".to_string()],
+            chance: 2,
+            probability: Some(0.1),
+            ..Default::default()
+        };
+
+        assert_eq!(wrp.probability(), 0.1);
+        assert!((850..1_150).contains(&fired(&wrp, 10_000, 5)));
+
+        wrp.probability = Some(0.);
+        assert_eq!(fired(&wrp, 1_000, 6), 0);
+
+        wrp.probability = Some(2.);
+        assert_eq!(wrp.probability(), 1.);
+
+        let autrp = keyvalues_serde::from_str::<AutoRP>(
+            r#""autorp.txt"
+            {
+                "prepended_words" {}
+                "appended_words" {}
+                "word_replacements"
+                {
+                    "1"
+                    {
+                        "word" "foo"
+                        "probability" "0.25"
+                        "replacement" "bar"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(autrp.word_replacements["1"][0].probability(), 0.25);
+    }
+
+    #[test]
+    fn failed_roll_falls_through() {
+        let autrp = AutoRP {
+            word_replacements: IndexMap::from([(
+                "1".to_string(),
+                vec![
+                    WordReplacement {
+                        word: IndexSet::from(["foo".to_string()]),
+                        replacement: vec!["rare".to_string()],
+                        chance: 4,
+                        ..Default::default()
+                    },
+                    WordReplacement {
+                        word: IndexSet::from(["foo".to_string()]),
+                        replacement: vec!["common".to_string()],
+                        chance: 1,
+                        ..Default::default()
+                    },
+                ],
+            )]),
+            ..Default::default()
+        };
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let rare = (0..10_000)
+            .filter(|_| autrp.translate_with_rng("foo", &mut rng) == "rare")
+            .count();
+        assert!((2_300..2_700).contains(&rare));
     }
 }
@@ -0,0 +1,93 @@
+//! Helpers for detecting the letter case of a source word and copying it onto
+//! its replacement.
+
+/// The casing of a word, as far as translation cares about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Casing {
+    /// Anything that is not title or upper case. Replacements are left as written.
+    #[default]
+    Lower,
+    /// The first letter is upper case, e.g. `Hello`.
+    Title,
+    /// Every letter is upper case and there is more than one of them, e.g. `HELLO`.
+    Upper,
+}
+
+impl Casing {
+    /// Detects the casing of the given word.
+    pub fn of(word: &str) -> Self {
+        let mut letters = word.chars().filter(|c| c.is_alphabetic());
+        let first = match letters.next() {
+            None => return Casing::Lower,
+            Some(c) => c,
+        };
+
+        if !first.is_uppercase() {
+            return Casing::Lower;
+        }
+
+        let mut rest = letters.peekable();
+        if rest.peek().is_some() && rest.all(|c| c.is_uppercase()) {
+            Casing::Upper
+        } else {
+            Casing::Title
+        }
+    }
+
+    /// Applies this casing to `text`.
+    ///
+    /// Lower case leaves the text untouched so that proper nouns in the
+    /// replacement (`Odin`, `I pray you`) survive.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Casing::Lower => text.to_string(),
+            Casing::Upper => text.to_uppercase(),
+            Casing::Title => capitalize(text),
+        }
+    }
+}
+
+/// Upper cases the first letter of `text`, leaving everything else alone.
+pub fn capitalize(text: &str) -> String {
+    match text.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        None => text.to_string(),
+        Some((i, c)) => {
+            let mut res = String::with_capacity(text.len());
+            res.push_str(&text[..i]);
+            res.extend(c.to_uppercase());
+            res.push_str(&text[i + c.len_utf8()..]);
+            res
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_casing() {
+        assert_eq!(Casing::of("hello"), Casing::Lower);
+        assert_eq!(Casing::of("Hello"), Casing::Title);
+        assert_eq!(Casing::of("HELLO"), Casing::Upper);
+        assert_eq!(Casing::of("I"), Casing::Title);
+        assert_eq!(Casing::of("DON'T"), Casing::Upper);
+        assert_eq!(Casing::of("hELLO"), Casing::Lower);
+        assert_eq!(Casing::of("!"), Casing::Lower);
+    }
+
+    #[test]
+    fn applies_casing() {
+        assert_eq!(Casing::Lower.apply("thee"), "thee");
+        assert_eq!(Casing::Lower.apply("Odin"), "Odin");
+        assert_eq!(
+            Casing::Title.apply("many good thanks to you"),
+            "Many good thanks to you"
+        );
+        assert_eq!(Casing::Title.apply("'tis"), "'Tis");
+        assert_eq!(
+            Casing::Upper.apply("many good thanks to you"),
+            "MANY GOOD THANKS TO YOU"
+        );
+    }
+}
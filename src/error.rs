@@ -0,0 +1,99 @@
+//! Errors that can occur while loading a rules file.
+
+use std::fmt;
+use std::io;
+
+use keyvalues_serde::parser::error::Error as ParserError;
+use pest::error::LineColLocation;
+
+use crate::template_string::TemplateError;
+
+/// The different ways loading an `AutoRP` rules file can fail.
+#[derive(Debug)]
+pub enum AutoRPError {
+    /// The text is not valid keyvalues.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The text is valid keyvalues, but does not have the shape of a rules file.
+    Deserialize(String),
+    /// The rules could not be read.
+    Io(io::Error),
+    /// A required section is absent.
+    MissingSection(&'static str),
+    /// A rule matches words but has nothing to replace them with. `rule` is
+    /// the index of the rule within its section.
+    EmptyReplacements { rule: usize, word: String },
+    /// A template can not be expanded, because it contains itself or nests
+    /// too deeply.
+    Template(TemplateError),
+}
+
+impl fmt::Display for AutoRPError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoRPError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "syntax error at {}:{}: {}", line, column, message),
+            AutoRPError::Deserialize(message) => write!(f, "invalid rules: {}", message),
+            AutoRPError::Io(err) => write!(f, "failed to read rules: {}", err),
+            AutoRPError::MissingSection(section) => write!(f, "missing section \"{}\"", section),
+            AutoRPError::EmptyReplacements { rule, word } => write!(
+                f,
+                "rule {} for \"{}\" has no replacements to choose from",
+                rule, word
+            ),
+            AutoRPError::Template(err) => write!(f, "invalid templates: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AutoRPError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AutoRPError::Io(err) => Some(err),
+            AutoRPError::Template(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AutoRPError {
+    fn from(err: io::Error) -> Self {
+        AutoRPError::Io(err)
+    }
+}
+
+impl From<TemplateError> for AutoRPError {
+    fn from(err: TemplateError) -> Self {
+        AutoRPError::Template(err)
+    }
+}
+
+impl From<keyvalues_serde::Error> for AutoRPError {
+    fn from(err: keyvalues_serde::Error) -> Self {
+        match err {
+            keyvalues_serde::Error::Io(err) => AutoRPError::Io(err),
+            keyvalues_serde::Error::Parse(ParserError::EscapedParseError(err)) => syntax(&err),
+            keyvalues_serde::Error::Parse(ParserError::RawParseError(err)) => syntax(&err),
+            err => AutoRPError::Deserialize(err.to_string()),
+        }
+    }
+}
+
+/// Converts a `pest` error into a `Syntax` error, keeping where it started.
+fn syntax<R: pest::RuleType>(err: &pest::error::Error<R>) -> AutoRPError {
+    let (line, column) = match err.line_col {
+        LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+    };
+
+    AutoRPError::Syntax {
+        line,
+        column,
+        message: err.variant.message().into_owned(),
+    }
+}
@@ -0,0 +1,606 @@
+//! Records why a translation came out the way it did: which rule produced
+//! each part of the output, and which dice were rolled on the way.
+
+use std::fmt;
+use std::ops::Range;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::autorp::MatchKind;
+
+/// What produced a span of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// A word replacement, by its index in the list of rules and the way it
+    /// matched
+    Rule { index: usize, kind: MatchKind },
+    /// A verb conjugated to agree with its subject
+    Conjugation,
+    /// A pronoun chosen by its place in the sentence
+    Pronoun,
+    /// An article changed to agree with the word after it
+    Article,
+    /// An interjection added before a sentence
+    Interjection,
+    /// An exclamation added after a sentence
+    Exclamation,
+    /// A word left as it was, because the roll of every rule matching it
+    /// failed
+    Kept,
+}
+
+/// A roll of the dice for a rule with a `chance` or `probability`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roll {
+    /// The index of the rule in the list of rules
+    pub rule: usize,
+    /// The probability the roll had of passing
+    pub probability: f64,
+    /// Whether the rule was applied
+    pub passed: bool,
+}
+
+/// A part of the output which differs from the input.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// The bytes of the input which were translated. Empty for text which
+    /// was added, such as interjections.
+    #[wasm_bindgen(skip)]
+    pub source: Range<usize>,
+    /// The bytes of the output they were translated to
+    #[wasm_bindgen(skip)]
+    pub output: Range<usize>,
+    #[wasm_bindgen(skip)]
+    pub origin: Origin,
+    /// The rolls made for the rules matching these words, in the order they
+    /// were made
+    #[wasm_bindgen(skip)]
+    pub rolls: Vec<Roll>,
+    /// The replacement as written in the rules, if it contained templates
+    #[wasm_bindgen(skip)]
+    pub template: Option<String>,
+    /// `source` in UTF-16 code units, which is how JavaScript indexes
+    /// strings, once set by `Explanation::index_utf16`
+    #[wasm_bindgen(skip)]
+    pub source_utf16: Option<Range<usize>>,
+    /// `output` in UTF-16 code units, once set by `Explanation::index_utf16`
+    #[wasm_bindgen(skip)]
+    pub output_utf16: Option<Range<usize>>,
+}
+
+/// The getters give offsets in UTF-16 code units, for slicing JavaScript
+/// strings with.
+#[wasm_bindgen]
+impl Span {
+    #[wasm_bindgen(getter)]
+    pub fn source_start(&self) -> usize {
+        self.source_utf16.as_ref().unwrap_or(&self.source).start
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn source_end(&self) -> usize {
+        self.source_utf16.as_ref().unwrap_or(&self.source).end
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn output_start(&self) -> usize {
+        self.output_utf16.as_ref().unwrap_or(&self.output).start
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn output_end(&self) -> usize {
+        self.output_utf16.as_ref().unwrap_or(&self.output).end
+    }
+
+    /// The index of the rule which produced the span, if any.
+    #[wasm_bindgen(getter)]
+    pub fn rule(&self) -> Option<usize> {
+        match self.origin {
+            Origin::Rule { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rolls(&self) -> Vec<Roll> {
+        self.rolls.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn template(&self) -> Option<String> {
+        self.template.clone()
+    }
+
+    /// A one line description of where the span came from, e.g.
+    /// `rule 12 (word); rolled 0.25 for rule 3: failed`.
+    pub fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Description {
+            origin: self.origin,
+            rolls: &self.rolls,
+            template: self.template.as_deref(),
+        }
+        .fmt(f)
+    }
+}
+
+/// Describes where a span or segment came from.
+struct Description<'a> {
+    origin: Origin,
+    rolls: &'a [Roll],
+    template: Option<&'a str>,
+}
+
+impl fmt::Display for Description<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.origin {
+            Origin::Rule { index, kind } => write!(f, "rule {} ({})", index, kind)?,
+            Origin::Conjugation => write!(f, "conjugation")?,
+            Origin::Pronoun => write!(f, "pronoun agreement")?,
+            Origin::Article => write!(f, "article agreement")?,
+            Origin::Interjection => write!(f, "interjection")?,
+            Origin::Exclamation => write!(f, "exclamation")?,
+            Origin::Kept => write!(f, "kept")?,
+        }
+        for roll in self.rolls {
+            let result = if roll.passed { "passed" } else { "failed" };
+            write!(
+                f,
+                "; rolled {:.2} for rule {}: {}",
+                roll.probability, roll.rule, result
+            )?;
+        }
+        if let Some(template) = self.template {
+            write!(f, "; from template {:?}", template)?;
+        }
+        Ok(())
+    }
+}
+
+/// A translation along with the spans of the output which differ from the
+/// input, as returned by `AutoRP::translate_explained`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Explanation {
+    /// The translated text
+    pub output: String,
+    /// The changed parts of the output, in order
+    pub spans: Vec<Span>,
+}
+
+impl Explanation {
+    /// Maps a position in the output to the corresponding position in the
+    /// input. A position within a span maps to the start of its source.
+    fn source_at(&self, out: usize) -> usize {
+        let mut src = out;
+        for span in &self.spans {
+            if span.output.end <= out {
+                src = span.source.end + (out - span.output.end);
+            } else if span.output.start <= out {
+                return span.source.start;
+            } else {
+                break;
+            }
+        }
+        src
+    }
+
+    /// Applies `edit` to the output, moving the spans after it. An edit
+    /// inside a span becomes part of that span, otherwise it is given a span
+    /// of its own if it has an origin.
+    pub(crate) fn apply(&mut self, edit: Edit) {
+        let Edit {
+            range,
+            text,
+            origin,
+        } = edit;
+        let delta = text.len() as isize - range.len() as isize;
+        let shift = |n: usize| n.checked_add_signed(delta).unwrap_or_default();
+        let src = self.source_at(range.start);
+
+        let mut contained = false;
+        for span in &mut self.spans {
+            let inside = span.output.start <= range.start
+                && range.end <= span.output.end
+                && (!range.is_empty()
+                    || span.output.start < range.start && range.start < span.output.end);
+            if inside {
+                span.output.end = shift(span.output.end);
+                contained = true;
+            } else if span.output.start >= range.end {
+                span.output = shift(span.output.start)..shift(span.output.end);
+            }
+        }
+
+        if let (Some(origin), false) = (origin, contained) {
+            let at = self.spans.partition_point(|s| s.output.start < range.start);
+            self.spans.insert(
+                at,
+                Span {
+                    source: src..src + range.len(),
+                    output: range.start..range.start + text.len(),
+                    origin,
+                    rolls: Vec::new(),
+                    template: None,
+                    source_utf16: None,
+                    output_utf16: None,
+                },
+            );
+        }
+        self.output.replace_range(range, &text);
+    }
+
+    /// Applies `edits`, which must be in order and must not overlap.
+    pub(crate) fn apply_all(&mut self, edits: Vec<Edit>) {
+        // From the last, so that the ranges of the others stay valid.
+        for edit in edits.into_iter().rev() {
+            self.apply(edit);
+        }
+    }
+
+    /// Sets the UTF-16 offsets of every span, the source ones within `input`.
+    pub(crate) fn index_utf16(&mut self, input: &str) {
+        let mut source = Utf16Offsets::new(input);
+        let mut output = Utf16Offsets::new(&self.output);
+        for span in &mut self.spans {
+            span.source_utf16 = Some(source.range(&span.source));
+            span.output_utf16 = Some(output.range(&span.output));
+        }
+    }
+
+    /// The bytes of the output which differ from the input.
+    pub(crate) fn changed(&self) -> Vec<Range<usize>> {
+        self.spans
+            .iter()
+            .filter(|span| span.origin != Origin::Kept)
+            .map(|span| span.output.clone())
+            .collect()
+    }
+
+    /// Splits the translation of `input` into segments: the spans, and the
+    /// text between them. A span overlapping the one before it is merged
+    /// into it, so that every part of the input is in exactly one segment.
+    pub fn segments(&self, input: &str) -> Vec<Segment> {
+        let mut segments: Vec<Segment> = Vec::with_capacity(self.spans.len() * 2 + 1);
+        let (mut src, mut out) = (0, 0);
+        // Where the segment of the last span starts
+        let mut last = (0, 0);
+
+        for span in &self.spans {
+            if span.source.start < src || span.output.start < out {
+                (src, out) = (src.max(span.source.end), out.max(span.output.end));
+                let segment = segments.last_mut().expect("a span came before");
+                segment.original = input[last.0..src].to_string();
+                let origin = segment.origin;
+                segment.set(self.output[last.1..out].to_string(), origin);
+                segment.rolls.extend_from_slice(&span.rolls);
+                continue;
+            }
+            if span.output.start > out {
+                segments.push(Segment::new(
+                    &input[src..span.source.start],
+                    &self.output[out..span.output.start],
+                    None,
+                ));
+            }
+            let mut segment = Segment::new(
+                &input[span.source.clone()],
+                &self.output[span.output.clone()],
+                Some(span.origin),
+            );
+            segment.rolls.clone_from(&span.rolls);
+            segment.template.clone_from(&span.template);
+            segments.push(segment);
+            last = (span.source.start, span.output.start);
+            (src, out) = (span.source.end, span.output.end);
+        }
+
+        if out < self.output.len() {
+            segments.push(Segment::new(&input[src..], &self.output[out..], None));
+        }
+        segments
+    }
+}
+
+/// A piece of a translation: either a change, along with the rule behind it,
+/// or text in between which was left alone.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The input the segment was translated from, empty for text which was
+    /// added, such as interjections
+    pub original: String,
+    /// The output for the segment
+    pub translated: String,
+    /// Whether the output differs from the input
+    pub changed: bool,
+    /// The index of the rule which produced the segment, if any
+    #[wasm_bindgen(js_name = ruleId)]
+    pub rule_id: Option<usize>,
+    /// What produced the segment, `None` for text left alone
+    #[wasm_bindgen(skip)]
+    pub origin: Option<Origin>,
+    /// The rolls made for the rules matching the segment
+    #[wasm_bindgen(skip)]
+    pub rolls: Vec<Roll>,
+    /// The replacement as written in the rules, if it contained templates
+    #[wasm_bindgen(skip)]
+    pub template: Option<String>,
+}
+
+impl Segment {
+    pub(crate) fn new(original: &str, translated: &str, origin: Option<Origin>) -> Self {
+        let mut segment = Segment {
+            original: original.to_string(),
+            translated: String::new(),
+            changed: false,
+            rule_id: None,
+            origin: None,
+            rolls: Vec::new(),
+            template: None,
+        };
+        segment.set(translated.to_string(), origin);
+        segment
+    }
+
+    /// Replaces the output of the segment.
+    pub(crate) fn set(&mut self, translated: String, origin: Option<Origin>) {
+        self.changed = translated != self.original;
+        self.translated = translated;
+        self.rule_id = match origin {
+            Some(Origin::Rule { index, .. }) => Some(index),
+            _ => None,
+        };
+        self.origin = origin;
+    }
+}
+
+#[wasm_bindgen]
+impl Segment {
+    /// Whether `AutoRP::reroll_segment` can give the segment another
+    /// translation: it must come from a rule or an interjection or
+    /// exclamation, or have been kept after a failed roll.
+    pub fn can_reroll(&self) -> bool {
+        matches!(
+            self.origin,
+            Some(Origin::Rule { .. } | Origin::Kept | Origin::Interjection | Origin::Exclamation)
+        )
+    }
+
+    /// A one line description of where the segment came from, like
+    /// `Span::describe`, or `None` for text left alone.
+    pub fn describe(&self) -> Option<String> {
+        let description = Description {
+            origin: self.origin?,
+            rolls: &self.rolls,
+            template: self.template.as_deref(),
+        };
+        Some(description.to_string())
+    }
+}
+
+/// Converts byte offsets into a text to offsets in UTF-16 code units. Only
+/// the text between one offset and the next is looked at, so converting them
+/// in order takes linear time.
+pub(crate) struct Utf16Offsets<'a> {
+    text: &'a str,
+    byte: usize,
+    unit: usize,
+}
+
+impl<'a> Utf16Offsets<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            byte: 0,
+            unit: 0,
+        }
+    }
+
+    pub(crate) fn at(&mut self, byte: usize) -> usize {
+        if byte >= self.byte {
+            self.unit += self.text[self.byte..byte].encode_utf16().count();
+        } else {
+            self.unit -= self.text[byte..self.byte].encode_utf16().count();
+        }
+        self.byte = byte;
+        self.unit
+    }
+
+    pub(crate) fn range(&mut self, range: &Range<usize>) -> Range<usize> {
+        self.at(range.start)..self.at(range.end)
+    }
+}
+
+/// A change to a piece of text, made after it was translated.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Edit {
+    /// The bytes replaced
+    pub range: Range<usize>,
+    /// The text they are replaced with
+    pub text: String,
+    /// What the change is reported as by `Explanation`, if it is reported
+    pub origin: Option<Origin>,
+}
+
+/// Applies `edit`, a change to the joined output of `segments`, to the
+/// segment it falls in. Text left alone is split around the edit, which is
+/// given a segment of its own. Edits across several segments are ignored.
+pub(crate) fn edit_segments(segments: &mut Vec<Segment>, edit: Edit) {
+    let mut end = 0;
+    let Some(i) = segments.iter().position(|segment| {
+        end += segment.translated.len();
+        edit.range.start < end
+    }) else {
+        return;
+    };
+    let segment = &mut segments[i];
+    let start = end - segment.translated.len();
+    if edit.range.end > end {
+        return;
+    }
+
+    let range = edit.range.start - start..edit.range.end - start;
+    if segment.origin.is_some() {
+        let mut translated = segment.translated.clone();
+        translated.replace_range(range, &edit.text);
+        let origin = segment.origin;
+        segment.set(translated, origin);
+        return;
+    }
+
+    // Text left alone only differs from the input in its casing, if at all,
+    // so the edit covers the same part of both.
+    let t = &segment.translated;
+    let o = match segment.original.len() == t.len() {
+        true => &segment.original,
+        false => t,
+    };
+    let split = [
+        Segment::new(&o[..range.start], &t[..range.start], None),
+        Segment::new(&o[range.clone()], &edit.text, edit.origin),
+        Segment::new(&o[range.end..], &t[range.end..], None),
+    ];
+    segments.splice(
+        i..=i,
+        split.into_iter().filter(|s| !s.translated.is_empty()),
+    );
+}
+
+/// Applies `edits`, which must be in order and must not overlap, to `text`.
+pub(crate) fn apply_edits(text: &str, edits: &[Edit]) -> String {
+    let mut res =
+        String::with_capacity(text.len() + edits.iter().map(|e| e.text.len()).sum::<usize>());
+    let mut copied = 0;
+    for edit in edits {
+        res.push_str(&text[copied..edit.range.start]);
+        res.push_str(&edit.text);
+        copied = edit.range.end;
+    }
+    res.push_str(&text[copied..]);
+    res
+}
+
+/// The bytes of `apply_edits(text, edits)` holding the text of each edit.
+pub(crate) fn edited_ranges(edits: &[Edit]) -> Vec<Range<usize>> {
+    let mut shift = 0isize;
+    edits
+        .iter()
+        .map(|edit| {
+            let start = edit.range.start.saturating_add_signed(shift);
+            shift += edit.text.len() as isize - edit.range.len() as isize;
+            start..start + edit.text.len()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(source: Range<usize>, output: Range<usize>) -> Span {
+        Span {
+            source,
+            output,
+            origin: Origin::Conjugation,
+            rolls: Vec::new(),
+            template: None,
+            source_utf16: None,
+            output_utf16: None,
+        }
+    }
+
+    #[test]
+    fn edits_move_spans() {
+        // "he runs fast" -> "he runneth fast"
+        let mut explanation = Explanation {
+            output: "he runneth fast".to_string(),
+            spans: vec![span(3..7, 3..10)],
+        };
+        assert_eq!(explanation.source_at(11), 8);
+        assert_eq!(explanation.source_at(5), 3);
+
+        explanation.apply(Edit {
+            range: 0..0,
+            text: "Hark! ".to_string(),
+            origin: Some(Origin::Interjection),
+        });
+        explanation.apply(Edit {
+            range: 6..7,
+            text: "H".to_string(),
+            origin: None,
+        });
+        explanation.apply(Edit {
+            range: 12..13,
+            text: "N".to_string(),
+            origin: None,
+        });
+
+        assert_eq!(explanation.output, "Hark! He runNeth fast");
+        assert_eq!(explanation.spans[0].source, 0..0);
+        assert_eq!(explanation.spans[0].output, 0..6);
+        assert_eq!(explanation.spans[1].output, 9..16);
+        assert_eq!(explanation.spans.len(), 2);
+    }
+
+    #[test]
+    fn overlapping_spans_are_merged() {
+        // "does you" -> "doeseth thou", with a conjugation over the rule
+        let explanation = Explanation {
+            output: "doeseth thou".to_string(),
+            spans: vec![span(0..4, 0..7), span(0..4, 0..4), span(5..8, 8..12)],
+        };
+        let segments = explanation.segments("does you");
+        let parts: Vec<_> = segments
+            .iter()
+            .map(|s| (s.original.as_str(), s.translated.as_str()))
+            .collect();
+        assert_eq!(
+            parts,
+            vec![("does", "doeseth"), (" ", " "), ("you", "thou")]
+        );
+    }
+
+    #[test]
+    fn offsets_count_utf16_units() {
+        // "café 𝔣ool" -> "café 𝔣ool!"
+        let mut explanation = Explanation {
+            output: "café 𝔣ool!".to_string(),
+            spans: vec![span(13..13, 13..14), span(0..5, 0..5)],
+        };
+        explanation.index_utf16("café 𝔣ool");
+        let span = &explanation.spans[0];
+        assert_eq!((span.source_start(), span.source_end()), (10, 10));
+        assert_eq!((span.output_start(), span.output_end()), (10, 11));
+        let span = &explanation.spans[1];
+        assert_eq!((span.source_start(), span.source_end()), (0, 4));
+
+        let mut offsets = Utf16Offsets::new("é𝔣x");
+        assert_eq!(offsets.range(&(2..7)), 1..4);
+        assert_eq!(offsets.at(0), 0);
+    }
+
+    #[test]
+    fn applies_edits() {
+        let edits = [
+            Edit {
+                range: 0..1,
+                text: "an".to_string(),
+                origin: None,
+            },
+            Edit {
+                range: 8..8,
+                text: "!".to_string(),
+                origin: None,
+            },
+        ];
+        assert_eq!(apply_edits("a idiot.", &edits), "an idiot.!");
+    }
+}
@@ -1,3 +1,13 @@
+pub mod alternatives;
 pub mod arp_tokenizer;
+pub mod articles;
 pub mod autorp;
+pub mod casing;
+pub mod error;
+pub mod explain;
+pub mod lint;
+pub mod morphology;
+pub mod pronouns;
+mod rule_index;
+pub mod sentences;
 pub mod template_string;
@@ -0,0 +1,104 @@
+//! Finds rules which can never be applied, because other rules always match
+//! the same words first.
+
+use std::fmt;
+
+use crate::autorp::{MatchKind, WordReplacement};
+use crate::rule_index::RuleIndex;
+
+/// A rule which is never applied, as found by `AutoRP::shadowed_rules`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shadowed {
+    /// The index of the rule in the list of rules
+    pub rule: usize,
+    /// The first words the rule matches, to help find it in the rules file
+    pub words: String,
+    /// The rules which are always applied instead, in order
+    pub by: Vec<usize>,
+}
+
+impl fmt::Display for Shadowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by: Vec<String> = self.by.iter().map(usize::to_string).collect();
+        write!(
+            f,
+            "rule {} ({:?}) is never applied, as rule {} always matches first",
+            self.rule,
+            self.words,
+            by.join(" or ")
+        )
+    }
+}
+
+/// The words `rule` matches, each with the way it matches them.
+fn patterns(rule: &WordReplacement) -> Vec<(Vec<&str>, MatchKind)> {
+    let words: Vec<Vec<&str>> = if !rule.phrase.is_empty() {
+        rule.phrase
+            .iter()
+            .map(|p| p.iter().map(String::as_str).collect())
+            .collect()
+    } else if !rule.prev.is_empty() {
+        rule.prev
+            .iter()
+            .flat_map(|prev| rule.word.iter().map(move |word| vec![prev.as_str(), word]))
+            .collect()
+    } else {
+        rule.word
+            .iter()
+            .chain(&rule.word_plural)
+            .map(|word| vec![word.as_str()])
+            .collect()
+    };
+
+    words
+        .into_iter()
+        .filter_map(|words| {
+            let kind = rule.matches(&words)?;
+            Some((words, kind))
+        })
+        .collect()
+}
+
+/// Finds the rules which are shadowed: whatever words they match, another
+/// rule which is always applied matches them too and is tried first.
+pub(crate) fn shadowed_rules(rules: &[WordReplacement], index: &RuleIndex) -> Vec<Shadowed> {
+    let mut shadowed = Vec::new();
+
+    for (i, rule) in rules.iter().enumerate() {
+        let patterns = patterns(rule);
+        let mut by = Vec::new();
+
+        let all_shadowed = !patterns.is_empty()
+            && patterns.iter().all(|(words, kind)| {
+                let precedence = (rule.precedence(*kind), i);
+                let first = index
+                    .candidates(words)
+                    .into_iter()
+                    .filter(|&j| j != i && rules[j].probability() >= 1.)
+                    .filter_map(|j| {
+                        let kind = rules[j].matches(words)?;
+                        Some((rules[j].precedence(kind), j))
+                    })
+                    .filter(|other| *other < precedence)
+                    .min();
+
+                let Some((_, j)) = first else {
+                    return false;
+                };
+                if !by.contains(&j) {
+                    by.push(j);
+                }
+                true
+            });
+
+        if all_shadowed {
+            by.sort_unstable();
+            shadowed.push(Shadowed {
+                rule: i,
+                words: patterns[0].0.join(" "),
+                by,
+            });
+        }
+    }
+    shadowed
+}
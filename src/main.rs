@@ -1,28 +1,363 @@
-use std::io::{self, Write};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use yeold::autorp::{AutoRP, AUTORP};
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{thread_rng, RngCore, SeedableRng};
+use yeold::autorp::AutoRP;
+use yeold::error::AutoRPError;
 
-fn main() {
-    let arp = keyvalues_serde::from_str::<AutoRP>(AUTORP).unwrap();
-    let stdin: io::Stdin = io::stdin();
-    let mut buf: String = String::with_capacity(1024);
-    let mut outbuf = String::with_capacity(1024);
+/// Translates text into the TF2 Medieval mode style.
+///
+/// Reads from standard input (or INPUT) and writes the translation to standard
+/// output (or --output). A prompt is only shown when reading from a terminal,
+/// so yeold can be used as a filter in pipelines.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// File to translate. Standard input is used when omitted or `-`.
+    input: Option<PathBuf>,
 
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
+    /// Write the translation to this file instead of standard output.
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Sometimes start the text with an interjection such as "Forsooth, ".
+    #[arg(long)]
+    prepend: bool,
+
+    /// Sometimes end the text with an exclamation such as "Anon!".
+    #[arg(long)]
+    append: bool,
+
+    /// How likely --prepend is to add an interjection to each sentence, from 0.0 to 1.0.
+    #[arg(long, value_name = "P", requires = "prepend")]
+    prepend_probability: Option<f64>,
+
+    /// How likely --append is to add an exclamation to each sentence, from 0.0 to 1.0.
+    #[arg(long, value_name = "P", requires = "append")]
+    append_probability: Option<f64>,
+
+    /// Seed the random choices so the same input always gives the same output.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Translate blank-line separated paragraphs as a whole instead of line by
+    /// line, so words split across lines still match.
+    #[arg(long)]
+    paragraphs: bool,
+
+    /// Translate the input as one continuous text instead of line by line, so
+    /// words split across lines still match. The input is streamed, so it may
+    /// be arbitrarily large.
+    #[arg(long, conflicts_with = "paragraphs")]
+    stream: bool,
+
+    /// Report on standard error which rule changed each part of every line,
+    /// and the dice rolled for it.
+    #[arg(long, conflicts_with = "stream")]
+    explain: bool,
+
+    /// Load extra rules from a file in the Autorp.txt format. May be repeated;
+    /// rules from later files take precedence over earlier ones.
+    #[arg(long = "rules", value_name = "PATH")]
+    rules: Vec<PathBuf>,
+
+    /// Use only the rules given with --rules instead of merging them over the
+    /// built-in dictionary.
+    #[arg(long, requires = "rules")]
+    no_default_rules: bool,
+
+    /// Check the rules instead of translating, listing on standard error any
+    /// rule which is never applied because others always match first.
+    #[arg(long)]
+    lint: bool,
+}
+
+/// Builds the `AutoRP` described by the command line arguments. Errors are
+/// given with the rules file they were found in, if any; the rules are only
+/// validated once every file is merged, since a file may rely on the others.
+fn load_rules(args: &Args) -> Result<AutoRP, (Option<PathBuf>, AutoRPError)> {
+    // `AutoRP::default()` loads the built-in dictionary, while the `Default`
+    // trait gives an empty instance.
+    let mut arp = match args.no_default_rules {
+        true => <AutoRP as Default>::default(),
+        false => AutoRP::default(),
+    };
+
+    for path in &args.rules {
+        let rules = File::open(path)
+            .map_err(AutoRPError::from)
+            .and_then(AutoRP::from_reader_unvalidated)
+            .map_err(|err| (Some(path.clone()), err))?;
+        arp.merge(rules);
+    }
+    arp.validate().map_err(|err| (None, err))?;
+
+    if let Some(probability) = args.prepend_probability {
+        arp.set_prepend_probability(probability);
+    }
+    if let Some(probability) = args.append_probability {
+        arp.set_append_probability(probability);
+    }
+
+    Ok(arp)
+}
+
+/// Translates text one piece at a time with the options from the command line.
+struct Translator {
+    arp: AutoRP,
+    rng: Box<dyn RngCore>,
+    prepend: bool,
+    append: bool,
+    explain: bool,
+}
+
+impl Translator {
+    /// Translates `text`, leaving blank text alone so that no interjections
+    /// are added to empty lines.
+    fn translate(&mut self, text: &str) -> String {
+        if text.trim().is_empty() {
+            return text.to_string();
+        }
+        if self.explain {
+            return self.explain(text);
+        }
+        self.arp
+            .translate_postprocess_with_rng(text, self.prepend, self.append, &mut self.rng)
+    }
+
+    /// Translates `text`, writing the explanation of every change made to
+    /// standard error.
+    fn explain(&mut self, text: &str) -> String {
+        let explanation = self.arp.translate_postprocess_explained_with_rng(
+            text,
+            self.prepend,
+            self.append,
+            &mut self.rng,
+        );
+        for span in &explanation.spans {
+            eprintln!(
+                "  {:?} -> {:?}: {}",
+                &text[span.source.clone()],
+                &explanation.output[span.output.clone()],
+                span
+            );
+        }
+        explanation.output
+    }
+}
+
+/// Splits the line ending off `line`, returning the line and its ending.
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let body = line.trim_end_matches(['\r', '\n']);
+    (body, &line[body.len()..])
+}
+
+/// Splits `text` into paragraphs, each paired with the line ending and blank
+/// lines that follow it. Joining every pair gives back `text`.
+fn split_paragraphs(text: &str) -> Vec<(&str, &str)> {
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    let mut body_end: Option<usize> = None;
+    let mut blank_seen = false;
+    let mut pos = 0;
+
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            blank_seen = true;
+        } else {
+            match body_end {
+                // Leading blank lines have no paragraph of their own.
+                None if pos > 0 => {
+                    paragraphs.push(("", &text[..pos]));
+                    start = pos;
+                }
+                Some(end) if blank_seen => {
+                    paragraphs.push((&text[start..end], &text[end..pos]));
+                    start = pos;
+                }
+                _ => {}
+            }
+            blank_seen = false;
+            body_end = Some(pos + split_line_ending(line).0.len());
+        }
+        pos += line.len();
+    }
+
+    match body_end {
+        Some(end) => paragraphs.push((&text[start..end], &text[end..])),
+        None if !text.is_empty() => paragraphs.push(("", text)),
+        None => {}
+    }
+    paragraphs
+}
 
-        let bytes: usize = stdin.read_line(&mut buf).unwrap();
+/// Translates the input line by line, keeping each line's ending.
+fn translate_lines(
+    translator: &mut Translator,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    interactive: bool,
+) -> io::Result<()> {
+    let mut buf = String::with_capacity(1024);
 
+    loop {
+        if interactive {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+
+        buf.clear();
         // EOF
-        if bytes == 0 {
+        if input.read_line(&mut buf)? == 0 {
             break;
         }
 
-        let input = buf.trim_end();
+        let (line, ending) = split_line_ending(&buf);
+        write!(output, "{}{}", translator.translate(line), ending)?;
+        if interactive {
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
 
-        println!("{}", arp.translate(input));
-        outbuf.clear();
-        buf.clear();
+/// Translates the whole input as one text, streaming it through.
+fn translate_text(
+    translator: &mut Translator,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    translator.arp.translate_postprocess_stream(
+        input,
+        output,
+        translator.prepend,
+        translator.append,
+        &mut translator.rng,
+    )
+}
+
+/// Translates the whole input one paragraph at a time.
+fn translate_paragraphs(
+    translator: &mut Translator,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+
+    for (paragraph, separator) in split_paragraphs(&text) {
+        write!(output, "{}{}", translator.translate(paragraph), separator)?;
+    }
+
+    Ok(())
+}
+
+/// Opens the input named on the command line, with `-` meaning standard input.
+fn open_input(path: Option<&Path>) -> io::Result<Box<dyn BufRead>> {
+    match path {
+        Some(path) if path != Path::new("-") => Ok(Box::new(BufReader::new(File::open(path)?))),
+        _ => Ok(Box::new(io::stdin().lock())),
+    }
+}
+
+/// Opens the output named on the command line, defaulting to standard output.
+fn open_output(path: Option<&Path>) -> io::Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(BufWriter::new(File::create(path)?))),
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+fn run(args: Args, arp: AutoRP) -> io::Result<()> {
+    let reads_stdin = args.input.as_deref().is_none_or(|p| p == Path::new("-"));
+    let interactive = reads_stdin && io::stdin().is_terminal() && !args.paragraphs && !args.stream;
+
+    let mut translator = Translator {
+        arp,
+        rng: match args.seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => Box::new(thread_rng()),
+        },
+        prepend: args.prepend,
+        append: args.append,
+        explain: args.explain,
+    };
+    let mut input = open_input(args.input.as_deref())?;
+    let mut output = open_output(args.output.as_deref())?;
+
+    if args.stream {
+        translate_text(&mut translator, &mut input, &mut output)?;
+    } else if args.paragraphs {
+        translate_paragraphs(&mut translator, &mut input, &mut output)?;
+    } else {
+        translate_lines(&mut translator, &mut input, &mut output, interactive)?;
+    }
+
+    output.flush()
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let arp = match load_rules(&args) {
+        Ok(arp) => arp,
+        Err((path, err)) => {
+            match path {
+                Some(path) => eprintln!("error: {}: {}", path.display(), err),
+                None => eprintln!("error: rules: {}", err),
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.lint {
+        let shadowed = arp.shadowed_rules();
+        for rule in &shadowed {
+            eprintln!("warning: {}", rule);
+        }
+        return match shadowed.is_empty() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        };
+    }
+
+    match run(args, arp) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Stop quietly when the reader on the other end of a pipe goes away.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_line_endings() {
+        assert_eq!(split_line_ending("hello\n"), ("hello", "\n"));
+        assert_eq!(split_line_ending("hello\r\n"), ("hello", "\r\n"));
+        assert_eq!(split_line_ending("hello"), ("hello", ""));
+    }
+
+    #[test]
+    fn splits_paragraphs() {
+        assert_eq!(split_paragraphs(""), vec![]);
+        assert_eq!(split_paragraphs("\n\n"), vec![("", "\n\n")]);
+        assert_eq!(
+            split_paragraphs("one\ntwo\r\n\r\nthree\n"),
+            vec![("one\ntwo", "\r\n\r\n"), ("three", "\n")]
+        );
+        assert_eq!(
+            split_paragraphs("\none\n  \n\ntwo"),
+            vec![("", "\n"), ("one", "\n  \n\n"), ("two", "")]
+        );
     }
 }
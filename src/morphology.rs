@@ -0,0 +1,234 @@
+//! Archaic verb conjugation: "he walks" becomes "he walketh" and "thou walk"
+//! becomes "thou walkest", without every verb being listed in the rules.
+
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::autorp::lowercase_set;
+
+/// Conjugates the verb following one of a set of subjects by adding a suffix.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Conjugation {
+    /// The words after which a verb is conjugated, as they appear in the
+    /// translated output (lower case), e.g. `thou` rather than `you`
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub subject: IndexSet<String>,
+
+    /// The ending a verb must have to be conjugated, removed before the
+    /// suffix is added. `s` also handles `-es` and `-ies`, as in `watches`
+    /// and `tries`. When empty, the verb is expected in its base form.
+    #[serde(default)]
+    pub ending: String,
+
+    /// The suffix added to the base form of the verb, e.g. `eth` or `est`
+    pub suffix: String,
+
+    /// Words which are never conjugated (lower case), such as adverbs that
+    /// happen to follow the subject
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub except: IndexSet<String>,
+
+    /// Verbs which are always conjugated after the subject, in their base
+    /// form (lower case), e.g. `walk` for both "he walks" and "thou walk"
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub verb: IndexSet<String>,
+
+    /// Words which show that the word before them is a verb, such as `the`
+    /// or `him` (lower case). Any other word after the subject is only
+    /// conjugated if it is listed under `verb` or `irregular`.
+    #[serde(default, deserialize_with = "lowercase_set")]
+    pub object: IndexSet<String>,
+
+    /// Verbs conjugated by lookup instead, from the form in the input to the
+    /// archaic form (lower case), e.g. `has` to `hath`
+    #[serde(default, deserialize_with = "lowercase_keys")]
+    pub irregular: IndexMap<String, String>,
+}
+
+/// Deserializes a map in file order, lower casing its keys so they can be
+/// matched case-insensitively.
+fn lowercase_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<IndexMap<String, String>, D::Error> {
+    let map = IndexMap::<String, String>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(k, v)| (k.to_lowercase(), v))
+        .collect())
+}
+
+impl Conjugation {
+    /// Conjugates `word`, which follows `subject` in the output and comes
+    /// before `next`, if anything. All are expected to be lower case. Returns
+    /// `None` if this conjugation does not apply, or if nothing shows `word`
+    /// to be a verb.
+    pub fn conjugate(&self, subject: &str, word: &str, next: Option<&str>) -> Option<String> {
+        if !self.subject.contains(subject) || self.except.contains(word) {
+            return None;
+        }
+        if let Some(irregular) = self.irregular.get(word) {
+            return Some(irregular.clone());
+        }
+        if !is_conjugatable(word) {
+            return None;
+        }
+
+        let base = match self.ending.as_str() {
+            "" => word.to_string(),
+            "s" => strip_s(word)?,
+            ending => word.strip_suffix(ending)?.to_string(),
+        };
+        if !self.verb.contains(&base) && !next.is_some_and(|next| self.object.contains(next)) {
+            return None;
+        }
+        Some(add_suffix(&base, &self.suffix))
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+/// Returns `false` for words which are plainly not present tense verbs, or
+/// which are already conjugated.
+fn is_conjugatable(word: &str) -> bool {
+    word.len() > 1
+        && word.chars().all(|c| c.is_ascii_alphabetic())
+        && !["ed", "ing", "ly", "eth", "est"]
+            .iter()
+            .any(|e| word.ends_with(e))
+}
+
+/// Turns a third person singular verb into its base form: `walks` to `walk`,
+/// `watches` to `watch`, `tries` to `try`. Returns `None` if the word does not
+/// have such an ending.
+pub fn strip_s(word: &str) -> Option<String> {
+    if word.len() < 3 || word.ends_with("ss") || !word.ends_with('s') {
+        return None;
+    }
+
+    let stem = &word[..word.len() - 1];
+    if word.len() > 4 && word.ends_with("ies") {
+        return Some(format!("{}y", &word[..word.len() - 3]));
+    }
+    if ["sses", "shes", "ches", "xes", "zzes", "oes"]
+        .iter()
+        .any(|e| word.ends_with(e))
+    {
+        return Some(word[..word.len() - 2].to_string());
+    }
+    Some(stem.to_string())
+}
+
+/// Adds `suffix` to the base form of a verb, adjusting the spelling the way
+/// English does:
+///
+/// - a silent `e` merges with a suffix starting with `e`: `make` to `maketh`
+/// - `y` after a consonant becomes `i`: `try` to `triest`
+/// - the final consonant of a short verb is doubled: `run` to `runneth`
+pub fn add_suffix(base: &str, suffix: &str) -> String {
+    let chars: Vec<char> = base.chars().collect();
+    let suffix_vowel = suffix.starts_with(is_vowel);
+
+    match chars.as_slice() {
+        [.., 'e'] if suffix.starts_with('e') => format!("{}{}", base, &suffix[1..]),
+        [.., c, 'y'] if suffix_vowel && !is_vowel(*c) => {
+            format!("{}i{}", &base[..base.len() - 1], suffix)
+        }
+        [.., a, v, c]
+            if suffix_vowel
+                && !is_vowel(*a)
+                && is_vowel(*v)
+                && !is_vowel(*c)
+                && !matches!(c, 'w' | 'x' | 'y')
+                && chars.iter().filter(|c| is_vowel(**c)).count() == 1 =>
+        {
+            format!("{}{}{}", base, c, suffix)
+        }
+        _ => format!("{}{}", base, suffix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_s() {
+        assert_eq!(strip_s("walks").as_deref(), Some("walk"));
+        assert_eq!(strip_s("makes").as_deref(), Some("make"));
+        assert_eq!(strip_s("tries").as_deref(), Some("try"));
+        assert_eq!(strip_s("dies").as_deref(), Some("die"));
+        assert_eq!(strip_s("watches").as_deref(), Some("watch"));
+        assert_eq!(strip_s("kisses").as_deref(), Some("kiss"));
+        assert_eq!(strip_s("goes").as_deref(), Some("go"));
+        assert_eq!(strip_s("freezes").as_deref(), Some("freeze"));
+        assert_eq!(strip_s("kiss"), None);
+        assert_eq!(strip_s("walk"), None);
+        assert_eq!(strip_s("us"), None);
+    }
+
+    #[test]
+    fn adds_suffix() {
+        assert_eq!(add_suffix("walk", "eth"), "walketh");
+        assert_eq!(add_suffix("make", "eth"), "maketh");
+        assert_eq!(add_suffix("make", "est"), "makest");
+        assert_eq!(add_suffix("try", "est"), "triest");
+        assert_eq!(add_suffix("say", "eth"), "sayeth");
+        assert_eq!(add_suffix("run", "eth"), "runneth");
+        assert_eq!(add_suffix("stop", "est"), "stoppest");
+        assert_eq!(add_suffix("need", "est"), "needest");
+        assert_eq!(add_suffix("open", "eth"), "openeth");
+        assert_eq!(add_suffix("go", "est"), "goest");
+        assert_eq!(add_suffix("know", "est"), "knowest");
+    }
+
+    #[test]
+    fn conjugates() {
+        let third = Conjugation {
+            subject: IndexSet::from(["he".to_string()]),
+            ending: "s".to_string(),
+            suffix: "eth".to_string(),
+            except: IndexSet::from(["always".to_string()]),
+            verb: IndexSet::from(["run".to_string(), "walk".to_string()]),
+            object: IndexSet::from(["the".to_string()]),
+            irregular: IndexMap::from([("has".to_string(), "hath".to_string())]),
+        };
+
+        assert_eq!(
+            third.conjugate("he", "runs", None).as_deref(),
+            Some("runneth")
+        );
+        assert_eq!(third.conjugate("he", "has", None).as_deref(), Some("hath"));
+        assert_eq!(
+            third.conjugate("he", "wants", Some("the")).as_deref(),
+            Some("wanteth")
+        );
+        assert_eq!(third.conjugate("he", "plus", Some("she")), None);
+        assert_eq!(third.conjugate("he", "wants", None), None);
+        assert_eq!(third.conjugate("she", "runs", None), None);
+        assert_eq!(third.conjugate("he", "always", Some("the")), None);
+        assert_eq!(third.conjugate("he", "walked", Some("the")), None);
+        assert_eq!(third.conjugate("he", "can", None), None);
+        assert_eq!(third.conjugate("he", "don't", None), None);
+    }
+
+    #[test]
+    fn irregular_keys_are_lower_cased() {
+        let conjugation: Conjugation = keyvalues_serde::from_str(
+            r#""1"
+            {
+                "subject" "thou"
+                "suffix" "est"
+                "irregular" { "Are" "art" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            conjugation.conjugate("thou", "are", None).as_deref(),
+            Some("art")
+        );
+        assert_eq!(conjugation.conjugate("thou", "mine", None), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Chooses between thou, thee, thy, thine and ye for the modern second person
+//! pronouns, depending on where they stand in their clause.
+
+use crate::articles::wants_an;
+
+/// Words after which a new clause starts, so a following "you" is its subject.
+const CLAUSE_STARTERS: &[&str] = &[
+    "although", "and", "as", "because", "before", "but", "if", "nor", "once", "or", "since", "so",
+    "that", "then", "though", "unless", "until", "when", "whenever", "where", "whereas", "whether",
+    "while", "yet",
+];
+
+/// Verbs which may come before their subject in a question ("are you"), and
+/// which therefore have to agree with the pronoun.
+pub const AUXILIARIES: &[&str] = &[
+    "am", "are", "can", "could", "did", "do", "does", "had", "has", "have", "is", "may", "might",
+    "must", "shall", "should", "was", "were", "will", "would",
+];
+
+/// Verbs and adverbs which, following "you", show it to be the subject.
+/// Verbs which commonly follow an object ("let you go", "see you make it")
+/// are left out.
+const SUBJECT_VERBS: &[&str] = &[
+    "always",
+    "believe",
+    "didn't",
+    "don't",
+    "hate",
+    "hope",
+    "just",
+    "know",
+    "like",
+    "love",
+    "mean",
+    "never",
+    "said",
+    "say",
+    "seem",
+    "think",
+    "understand",
+    "want",
+    "wish",
+];
+
+/// Words which, following "you", show it to be plural.
+const PLURAL_MARKERS: &[&str] = &["all", "both", "folks", "guys", "lot", "people", "two"];
+
+/// Returns the archaic form of a second person pronoun, or `None` if `word` is
+/// not one.
+///
+/// `prev` is the word before it in the same clause, if any, and `next` the
+/// word directly after it. All three are expected to be lower case.
+pub fn archaic_pronoun(prev: Option<&str>, word: &str, next: Option<&str>) -> Option<&'static str> {
+    let pronoun = match word {
+        "you" | "u" => {
+            let subject = is_subject(prev, next);
+            match next.is_some_and(|n| PLURAL_MARKERS.contains(&n)) {
+                true if subject => "ye",
+                true => "you",
+                false if subject => "thou",
+                false => "thee",
+            }
+        }
+        "your" | "ur" if next.is_some_and(wants_an) => "thine",
+        "your" | "ur" => "thy",
+        "yours" => "thine",
+        "yourself" => "thyself",
+        "you're" | "you’re" | "youre" => "thou art",
+        "you've" | "you’ve" => "thou hast",
+        "you'll" | "you’ll" => "thou wilt",
+        "you'd" | "you’d" => "thou wouldst",
+        _ => return None,
+    };
+    Some(pronoun)
+}
+
+/// Returns `true` if "you" between `prev` and `next` is the subject of its
+/// clause rather than an object.
+fn is_subject(prev: Option<&str>, next: Option<&str>) -> bool {
+    match prev {
+        None => true,
+        Some(prev) if CLAUSE_STARTERS.contains(&prev) || AUXILIARIES.contains(&prev) => true,
+        Some(_) => next.is_some_and(|n| SUBJECT_VERBS.contains(&n) || AUXILIARIES.contains(&n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chooses_by_position() {
+        assert_eq!(archaic_pronoun(None, "you", Some("know")), Some("thou"));
+        assert_eq!(archaic_pronoun(Some("love"), "you", None), Some("thee"));
+        assert_eq!(
+            archaic_pronoun(Some("to"), "you", Some("now")),
+            Some("thee")
+        );
+        assert_eq!(
+            archaic_pronoun(Some("think"), "you", Some("are")),
+            Some("thou")
+        );
+        assert_eq!(
+            archaic_pronoun(Some("but"), "you", Some("left")),
+            Some("thou")
+        );
+        assert_eq!(
+            archaic_pronoun(Some("are"), "you", Some("sure")),
+            Some("thou")
+        );
+        assert_eq!(archaic_pronoun(None, "you", Some("all")), Some("ye"));
+        assert_eq!(
+            archaic_pronoun(Some("see"), "you", Some("all")),
+            Some("you")
+        );
+    }
+
+    #[test]
+    fn possessives_agree() {
+        assert_eq!(archaic_pronoun(None, "your", Some("sword")), Some("thy"));
+        assert_eq!(archaic_pronoun(None, "your", Some("eye")), Some("thine"));
+        assert_eq!(archaic_pronoun(None, "your", None), Some("thy"));
+        assert_eq!(archaic_pronoun(Some("is"), "yours", None), Some("thine"));
+        assert_eq!(archaic_pronoun(None, "you're", None), Some("thou art"));
+        assert_eq!(archaic_pronoun(None, "yonder", None), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Looks up the rules which may match a word, so that translating a word does
+//! not have to try every rule in the dictionary.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::autorp::WordReplacement;
+
+/// The rules of a dictionary, indexed by the last word they can match.
+#[derive(Debug, Default)]
+pub(crate) struct RuleIndex {
+    /// Rules matching a single word, a plural or a phrase, by the word they
+    /// end on
+    by_word: HashMap<String, Vec<usize>>,
+    /// Rules matching a previous word followed by a word, by the word and
+    /// then the previous word
+    by_pair: HashMap<String, HashMap<String, Vec<usize>>>,
+    /// The most words any rule can match at once
+    max_len: usize,
+}
+
+impl RuleIndex {
+    pub(crate) fn new(rules: &[WordReplacement]) -> Self {
+        let mut index = RuleIndex::default();
+
+        for (i, rule) in rules.iter().enumerate() {
+            index.max_len = index.max_len.max(rule.max_len());
+
+            if !rule.phrase.is_empty() {
+                for last in rule.phrase.iter().filter_map(|p| p.last()) {
+                    index.by_word.entry(last.clone()).or_default().push(i);
+                }
+            } else if !rule.prev.is_empty() {
+                for word in &rule.word {
+                    let by_prev = index.by_pair.entry(word.clone()).or_default();
+                    for prev in &rule.prev {
+                        by_prev.entry(prev.clone()).or_default().push(i);
+                    }
+                }
+            } else {
+                for word in rule.word.iter().chain(&rule.word_plural) {
+                    index.by_word.entry(word.clone()).or_default().push(i);
+                }
+            }
+        }
+
+        for rules in index.by_word.values_mut() {
+            rules.dedup();
+        }
+        index
+    }
+
+    /// The indices of the rules which may match `words`, with the current word
+    /// last, in the order they appear in the dictionary.
+    pub(crate) fn candidates(&self, words: &[&str]) -> Vec<usize> {
+        let Some((&last, rest)) = words.split_last() else {
+            return Vec::new();
+        };
+
+        let mut candidates = self.by_word.get(last).cloned().unwrap_or_default();
+        if let Some(rules) = rest
+            .last()
+            .and_then(|prev| self.by_pair.get(last)?.get(*prev))
+        {
+            candidates.extend(rules);
+            candidates.sort_unstable();
+            candidates.dedup();
+        }
+        candidates
+    }
+
+    /// The most words any rule can match at once.
+    pub(crate) fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+/// A `RuleIndex` which is built the first time it is needed.
+#[derive(Debug, Default)]
+pub(crate) struct LazyRuleIndex(OnceLock<RuleIndex>);
+
+impl LazyRuleIndex {
+    /// Returns the index of `rules`, building it if needed. `rules` must be
+    /// the same every time until `reset` is called.
+    pub(crate) fn get(&self, rules: &[WordReplacement]) -> &RuleIndex {
+        self.0.get_or_init(|| RuleIndex::new(rules))
+    }
+
+    /// Forgets the index, after the rules changed.
+    pub(crate) fn reset(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+/// The index is derived from the rules, so it never makes two dictionaries
+/// differ.
+impl PartialEq for LazyRuleIndex {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexSet;
+
+    use super::*;
+
+    fn set(words: &[&str]) -> IndexSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_candidates() {
+        let rules = [
+            WordReplacement {
+                word: set(&["hello", "hi"]),
+                ..Default::default()
+            },
+            WordReplacement {
+                prev: set(&["thank"]),
+                word: set(&["you"]),
+                ..Default::default()
+            },
+            WordReplacement {
+                phrase: vec![vec!["how".into(), "are".into(), "you".into()]],
+                ..Default::default()
+            },
+            WordReplacement {
+                word: set(&["friend"]),
+                word_plural: set(&["friends"]),
+                ..Default::default()
+            },
+        ];
+        let index = RuleIndex::new(&rules);
+
+        assert_eq!(index.max_len(), 3);
+        assert_eq!(index.candidates(&["hi"]), vec![0]);
+        assert_eq!(index.candidates(&["thank", "you"]), vec![1, 2]);
+        assert_eq!(index.candidates(&["see", "you"]), vec![2]);
+        assert_eq!(index.candidates(&["friends"]), vec![3]);
+        assert!(index.candidates(&["thank"]).is_empty());
+        assert!(index.candidates(&[]).is_empty());
+    }
+}
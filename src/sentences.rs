@@ -0,0 +1,88 @@
+//! Splits text into sentences, so that interjections can be added to each.
+
+use crate::arp_tokenizer::{self, ArpToken};
+
+/// Words which are commonly abbreviated with a full stop that does not end
+/// the sentence, in lower case. Single letters, as in "e.g." or "J. Smith",
+/// are treated the same way.
+const ABBREVIATIONS: &[&str] = &[
+    "dr", "etc", "jr", "mr", "mrs", "ms", "mt", "no", "prof", "sr", "st", "vs",
+];
+
+/// Returns `true` if `punct` ends a sentence.
+fn is_terminator(punct: &str) -> bool {
+    punct.contains(['.', '!', '?', '…'])
+}
+
+/// Splits `text` into sentences. Each sentence keeps the whitespace after it,
+/// so joining them gives back `text`.
+///
+/// A sentence ends at a full stop, exclamation or question mark followed by
+/// whitespace, unless the full stop belongs to a common abbreviation such as
+/// "Mr.", and at every line break.
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let tokens = arp_tokenizer::tokenize(text);
+
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut prev_word: Option<&str> = None;
+    let mut prev_punct: Option<&str> = None;
+
+    for token in &tokens {
+        offset += token.as_str().len();
+        match token {
+            ArpToken::Word(word) => {
+                prev_word = Some(word);
+                prev_punct = None;
+            }
+            ArpToken::Punct(punct) => prev_punct = Some(punct),
+            ArpToken::Ws(ws) => {
+                let abbreviated = prev_punct == Some(".")
+                    && prev_word.is_some_and(|w| {
+                        w.chars().count() == 1 || ABBREVIATIONS.contains(&w.to_lowercase().as_str())
+                    });
+                let ends = ws.contains('\n')
+                    || prev_punct.is_some_and(|p| is_terminator(p) && !abbreviated);
+                if ends {
+                    sentences.push(&text[start..offset]);
+                    start = offset;
+                }
+                prev_word = None;
+                prev_punct = None;
+            }
+        }
+    }
+
+    if start < text.len() || sentences.is_empty() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_sentences() {
+        assert_eq!(
+            split_sentences("Hello there. How are you?! Fine"),
+            vec!["Hello there. ", "How are you?! ", "Fine"]
+        );
+        assert_eq!(
+            split_sentences("Ask Mr. Smith... or not!\nBye"),
+            vec!["Ask Mr. Smith... ", "or not!\n", "Bye"]
+        );
+        assert_eq!(
+            split_sentences("\"Quoted.\" Then"),
+            vec!["\"Quoted.\" ", "Then"]
+        );
+        assert_eq!(split_sentences("3.5 apples."), vec!["3.5 apples."]);
+        assert_eq!(
+            split_sentences("Fruit, e.g. apples. J. R. R. Tolkien"),
+            vec!["Fruit, e.g. apples. ", "J. R. R. Tolkien"]
+        );
+        assert_eq!(split_sentences(""), vec![""]);
+    }
+}
@@ -1,23 +1,65 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 
-use rand::thread_rng;
+use indexmap::IndexMap;
+use rand::seq::SliceRandom;
+use rand::RngCore;
 
-use crate::autorp::WordReplacement;
+use crate::casing::{capitalize, Casing};
 
-pub trait TemplateMap {
-    fn get<'a>(&self, input: &'a str) -> Option<Cow<str>>;
+/// How many levels of templates within templates are expanded by default.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// Why a template could not be expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A template contains itself. Holds the names of the templates involved,
+    /// starting and ending with the repeated one.
+    Cycle(Vec<String>),
+    /// Expanding the named template would nest deeper than `limit`.
+    TooDeep { name: String, limit: usize },
+    /// `text` contains a malformed `&{...}` placeholder.
+    Syntax { text: String, message: String },
+    /// `text` contains a placeholder naming a template which does not exist.
+    Unknown { name: String, text: String },
 }
 
-impl TemplateMap for Vec<WordReplacement> {
-    fn get<'a>(&self, input: &'a str) -> Option<Cow<str>> {
-        let mut rng = thread_rng();
-        for wr in self {
-            if let Some(s) = wr.simple_get(input, &mut rng) {
-                return Some(Cow::Borrowed(s));
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Cycle(names) => {
+                write!(f, "template \"{}\" contains itself: ", names[0])?;
+                let path: Vec<String> = names.iter().map(|n| format!("&{}", n)).collect();
+                write!(f, "{}", path.join(" -> "))
+            }
+            TemplateError::TooDeep { name, limit } => write!(
+                f,
+                "template \"{}\" nests more than {} levels deep",
+                name, limit
+            ),
+            TemplateError::Syntax { text, message } => write!(f, "{} in \"{}\"", message, text),
+            TemplateError::Unknown { name, text } => {
+                write!(f, "unknown template \"{}\" in \"{}\"", name, text)
             }
         }
-        None
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+pub trait TemplateMap {
+    /// Looks up `input`, using `rng` for any random choice the map has to make.
+    fn get(&self, input: &str, rng: &mut dyn RngCore) -> Option<Cow<'_, str>>;
+}
+
+/// A vocabulary of named lists in file order, where each lookup picks one
+/// entry at random.
+impl TemplateMap for IndexMap<String, Vec<String>> {
+    fn get(&self, input: &str, rng: &mut dyn RngCore) -> Option<Cow<'_, str>> {
+        self.get(input)?
+            .choose(rng)
+            .map(|s| Cow::Borrowed(s.as_str()))
     }
 }
 
@@ -28,130 +70,526 @@ impl TemplateMap for HashMap<String, String> {
     /// # Arguments
     ///
     /// * `input` - A string slice that holds the key to look up.
+    /// * `rng` - Unused, as a plain map has nothing to choose between.
     ///
     /// # Returns
     ///
     /// A `Cow<str>` that is either borrowed from the input or the value from the map.
-    fn get<'a>(&self, input: &'a str) -> Option<Cow<str>> {
+    fn get(&self, input: &str, _rng: &mut dyn RngCore) -> Option<Cow<'_, str>> {
         self.get(input).map(|s| Cow::Borrowed(s.as_str()))
     }
 }
 
-/// Parses a template string starting with '&' and returns the template and the rest of the string.
-///
-/// # Arguments
-///
-/// * `input` - A string slice that holds the template string.
-///
-/// # Returns
-///
-/// An `Option` containing a tuple with the template and the rest of the string if parsing is successful, otherwise `None`.
-fn parse_template(input: &str) -> Option<(&str, &str)> {
-    let mut it = input.char_indices();
-    let mut end = 0;
+/// A change applied to a template's value, written `&{name|modifier}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Modifier {
+    /// `upper`: upper cases the whole value
+    Upper,
+    /// `cap`: upper cases the first letter of the value
+    Cap,
+    /// `plural`: makes the last word of the value plural
+    Plural,
+}
 
-    if it.next()?.1 != '&' {
-        return None;
+impl Modifier {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upper" => Some(Modifier::Upper),
+            "cap" => Some(Modifier::Cap),
+            "plural" => Some(Modifier::Plural),
+            _ => None,
+        }
     }
 
-    while let Some((i, c)) = it.next() {
-        if !c.is_alphanumeric() {
-            break;
+    fn apply(self, text: &str) -> String {
+        match self {
+            Modifier::Upper => text.to_uppercase(),
+            Modifier::Cap => capitalize(text),
+            Modifier::Plural => pluralize(text),
         }
-        end = i;
     }
+}
+
+/// Makes the last word of `text` plural with the regular English endings.
+/// A word already ending in a single `s` is assumed to be plural already.
+fn pluralize(text: &str) -> String {
+    let lower = text.to_lowercase();
+    let suffix = if lower.ends_with("ss")
+        || lower.ends_with(['x', 'z'])
+        || lower.ends_with("ch")
+        || lower.ends_with("sh")
+    {
+        "es"
+    } else if lower.ends_with('s') || !lower.ends_with(char::is_alphabetic) {
+        ""
+    } else if lower.ends_with('y') && !lower[..lower.len() - 1].ends_with(['a', 'e', 'o', 'u']) {
+        "ies"
+    } else {
+        "s"
+    };
+
+    let stem = match suffix {
+        "ies" => &text[..text.len() - 1],
+        _ => text,
+    };
+    match Casing::of(text.rsplit(' ').next().unwrap_or(text)) {
+        Casing::Upper => format!("{}{}", stem, suffix.to_uppercase()),
+        _ => format!("{}{}", stem, suffix),
+    }
+}
+
+/// Template syntax found at the start of some text.
+#[derive(Debug, PartialEq)]
+enum Placeholder<'a> {
+    /// `&&`, which stands for a single `&`
+    Escape,
+    /// `&name` or `&{name|modifier|...}`
+    Template {
+        name: &'a str,
+        modifiers: Vec<Modifier>,
+    },
+}
+
+/// Parses the template syntax at the start of `input`, which begins with `&`,
+/// and returns it along with the rest of the string.
+///
+/// Returns `Ok(None)` if the `&` does not start any template syntax, in which
+/// case it stands for itself, and an error if it starts a malformed `&{...}`.
+fn parse_template(input: &str) -> Result<Option<(Placeholder<'_>, &str)>, TemplateError> {
+    let Some(after) = input.strip_prefix('&') else {
+        return Ok(None);
+    };
+
+    if let Some(rest) = after.strip_prefix('&') {
+        return Ok(Some((Placeholder::Escape, rest)));
+    }
+
+    let Some(braced) = after.strip_prefix('{') else {
+        let end = after
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(after.len());
+        return Ok(match end {
+            0 => None,
+            _ => Some((
+                Placeholder::Template {
+                    name: &after[..end],
+                    modifiers: Vec::new(),
+                },
+                &after[end..],
+            )),
+        });
+    };
+
+    let syntax = |message: String| TemplateError::Syntax {
+        text: input.to_string(),
+        message,
+    };
+    let end = braced
+        .find('}')
+        .ok_or_else(|| syntax("unterminated \"&{\"".to_string()))?;
+    let mut parts = braced[..end].split('|');
+    let name = parts.next().unwrap_or_default();
+    if name.is_empty() || !name.chars().all(char::is_alphanumeric) {
+        return Err(syntax(format!("invalid template name \"{}\"", name)));
+    }
+    let modifiers = parts
+        .map(|m| {
+            Modifier::from_name(m).ok_or_else(|| syntax(format!("unknown modifier \"{}\"", m)))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some((
+        Placeholder::Template { name, modifiers },
+        &braced[end + 1..],
+    )))
+}
+
+/// Iterates over the names of the placeholders in `input`, in order, stopping
+/// at the first malformed one.
+fn placeholders(input: &str) -> impl Iterator<Item = Result<&str, TemplateError>> {
+    let mut rest = input;
+    std::iter::from_fn(move || loop {
+        rest = &rest[rest.find('&')?..];
+        match parse_template(rest) {
+            Ok(Some((Placeholder::Template { name, .. }, tail))) => {
+                rest = tail;
+                return Some(Ok(name));
+            }
+            Ok(Some((Placeholder::Escape, tail))) => rest = tail,
+            Ok(None) => rest = &rest[1..],
+            Err(err) => {
+                rest = "";
+                return Some(Err(err));
+            }
+        }
+    })
+}
+
+/// Checks that every placeholder in `input` is well formed and names a
+/// template for which `known` returns `true`.
+pub fn check_placeholders(input: &str, known: impl Fn(&str) -> bool) -> Result<(), TemplateError> {
+    placeholders(input).try_for_each(|name| {
+        let name = name?;
+        match known(name) {
+            true => Ok(()),
+            false => Err(unknown(name, input)),
+        }
+    })
+}
 
-    match end {
-        0 | 1 => return None,
-        _ if end == input.len() - 1 => Some((input, "")),
-        _ => Some((&input[0..end + 1], &input[end + 1..])),
+fn unknown(name: &str, text: &str) -> TemplateError {
+    TemplateError::Unknown {
+        name: name.to_string(),
+        text: text.to_string(),
     }
 }
 
 /// Evaluates a template string by replacing placeholders with values from the provided map.
 ///
+/// Placeholders inside the substituted values are expanded in turn, up to
+/// `DEFAULT_MAX_DEPTH` levels deep. If expansion fails, the input is returned
+/// unchanged; use `template_expand` to find out why.
+///
 /// # Arguments
 ///
 /// * `input` - A string slice that holds the template string.
 /// * `map` - A reference to an object implementing the `TemplateMap` trait.
+/// * `rng` - The random number generator handed to `map` for its lookups.
 ///
 /// # Returns
 ///
 /// A `Cow<str>` that contains the evaluated string with placeholders replaced by corresponding values from the map.
-pub fn template_evaluate<'a>(mut input: &'a str, submap: &impl TemplateMap) -> Cow<'a, str> {
-    let mut res = match input.chars().position(|c| c == '&') {
-        None => return Cow::Borrowed(input),
-        Some(pos) => {
-            let mut res = String::with_capacity(input.len() * 11 / 10);
-            res.push_str(&input[..pos]);
-            input = &input[pos..];
-            res
-        }
-    };
+pub fn template_evaluate<'a>(
+    input: &'a str,
+    submap: &(impl TemplateMap + ?Sized),
+    rng: &mut dyn RngCore,
+) -> Cow<'a, str> {
+    template_expand(input, submap, DEFAULT_MAX_DEPTH, rng).unwrap_or(Cow::Borrowed(input))
+}
+
+/// Evaluates a template string like `template_evaluate`, expanding
+/// placeholders inside substituted values at most `max_depth` levels deep.
+///
+/// # Errors
+///
+/// Returns `TemplateError::Cycle` if a template ends up containing itself,
+/// `TemplateError::TooDeep` if the expansion nests deeper than `max_depth`,
+/// and `TemplateError::Syntax` if a placeholder is malformed.
+pub fn template_expand<'a>(
+    input: &'a str,
+    submap: &(impl TemplateMap + ?Sized),
+    max_depth: usize,
+    rng: &mut dyn RngCore,
+) -> Result<Cow<'a, str>, TemplateError> {
+    if !input.contains('&') {
+        return Ok(Cow::Borrowed(input));
+    }
+
+    let mut res = String::with_capacity(input.len() * 11 / 10);
+    expand_into(&mut res, input, submap, &mut Vec::new(), max_depth, rng)?;
+    Ok(Cow::Owned(res))
+}
 
-    while let Some(pos) = input.chars().position(|c| c == '&') {
+/// Writes `input` to `res` with its placeholders expanded. `stack` holds the
+/// names of the templates currently being expanded, outermost first.
+fn expand_into(
+    res: &mut String,
+    mut input: &str,
+    submap: &(impl TemplateMap + ?Sized),
+    stack: &mut Vec<String>,
+    max_depth: usize,
+    rng: &mut dyn RngCore,
+) -> Result<(), TemplateError> {
+    while let Some(pos) = input.find('&') {
         res.push_str(&input[..pos]);
         input = &input[pos..];
 
-        match {
-            parse_template(input).and_then(|(template, rest)| {
-                return submap.get(&template[1..]).map(|s| (s, rest));
-            })
-        } {
-            Some((s, rest)) => {
-                res.push_str(&s);
-                input = rest;
-            }
+        let (name, modifiers, rest) = match parse_template(input)? {
             None => {
-                res.push_str(&input[..1]);
+                res.push('&');
                 input = &input[1..];
+                continue;
+            }
+            Some((Placeholder::Escape, rest)) => {
+                res.push('&');
+                input = rest;
+                continue;
             }
+            Some((Placeholder::Template { name, modifiers }, rest)) => (name, modifiers, rest),
+        };
+        let Some(value) = submap.get(name, rng) else {
+            res.push_str(&input[..input.len() - rest.len()]);
+            input = rest;
+            continue;
+        };
+
+        if let Some(pos) = stack.iter().position(|n| n == name) {
+            let mut cycle = stack[pos..].to_vec();
+            cycle.push(name.to_string());
+            return Err(TemplateError::Cycle(cycle));
+        }
+        if stack.len() >= max_depth {
+            return Err(TemplateError::TooDeep {
+                name: name.to_string(),
+                limit: max_depth,
+            });
+        }
+
+        stack.push(name.to_string());
+        if modifiers.is_empty() {
+            expand_into(res, &value, submap, stack, max_depth, rng)?;
+        } else {
+            let mut expanded = String::new();
+            expand_into(&mut expanded, &value, submap, stack, max_depth, rng)?;
+            let modified = modifiers
+                .iter()
+                .fold(expanded, |text, modifier| modifier.apply(&text));
+            res.push_str(&modified);
         }
+        stack.pop();
+        input = rest;
     }
 
     res.push_str(input);
+    Ok(())
+}
+
+/// Checks that every template in `templates` can be fully expanded, whichever
+/// values are picked, without a cycle, an unknown template or nesting deeper
+/// than `max_depth`.
+pub fn check_templates(
+    templates: &IndexMap<String, Vec<String>>,
+    max_depth: usize,
+) -> Result<(), TemplateError> {
+    let mut depths = HashMap::new();
+
+    // In file order, so that the same file always reports the same problem.
+    for name in templates.keys() {
+        template_depth(name, templates, &mut Vec::new(), &mut depths, max_depth)?;
+    }
+    Ok(())
+}
+
+/// Returns how many levels deep expanding `name` can nest, remembering the
+/// answer for every template visited along the way in `depths`.
+fn template_depth<'a>(
+    name: &'a str,
+    templates: &'a IndexMap<String, Vec<String>>,
+    stack: &mut Vec<&'a str>,
+    depths: &mut HashMap<&'a str, usize>,
+    max_depth: usize,
+) -> Result<usize, TemplateError> {
+    if let Some(&depth) = depths.get(name) {
+        return Ok(depth);
+    }
+    if let Some(pos) = stack.iter().position(|&n| n == name) {
+        let mut cycle: Vec<String> = stack[pos..].iter().map(|n| n.to_string()).collect();
+        cycle.push(name.to_string());
+        return Err(TemplateError::Cycle(cycle));
+    }
+
+    stack.push(name);
+    let mut depth = 0;
+    for value in &templates[name] {
+        for inner in placeholders(value) {
+            let inner = inner?;
+            if !templates.contains_key(inner) {
+                return Err(unknown(inner, value));
+            }
+            depth = depth.max(template_depth(inner, templates, stack, depths, max_depth)?);
+        }
+    }
+    stack.pop();
 
-    return Cow::Owned(res);
+    let depth = depth + 1;
+    if depth > max_depth {
+        return Err(TemplateError::TooDeep {
+            name: name.to_string(),
+            limit: max_depth,
+        });
+    }
+    depths.insert(name, depth);
+    Ok(depth)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::thread_rng;
 
     #[test]
     fn test_template_evaluate_edge_cases() {
-        let map = HashMap::new();
+        let map: HashMap<String, String> = HashMap::new();
+        let mut rng = thread_rng();
 
-        assert_eq!(template_evaluate("", &map), Cow::Borrowed(""));
-        assert_eq!(template_evaluate("&", &map), Cow::Borrowed("&"));
-        assert_eq!(template_evaluate("&&", &map), Cow::Borrowed("&&"));
+        assert_eq!(template_evaluate("", &map, &mut rng), Cow::Borrowed(""));
+        assert_eq!(template_evaluate("&", &map, &mut rng), Cow::Borrowed("&"));
+        assert_eq!(template_evaluate("&&", &map, &mut rng), "&");
         assert_eq!(
-            template_evaluate("Hello, &name!", &map),
+            template_evaluate("Hello, &name!", &map, &mut rng),
             Cow::Borrowed("Hello, &name!")
         );
         assert_eq!(
-            template_evaluate("Hello, &!", &map),
+            template_evaluate("Hello, &!", &map, &mut rng),
             Cow::Borrowed("Hello, &!")
         );
     }
 
+    #[test]
+    fn test_template_evaluate_vocabulary() {
+        let mut map: IndexMap<String, Vec<String>> = IndexMap::new();
+        map.insert(
+            "god".to_string(),
+            vec!["Odin".to_string(), "Zeus".to_string()],
+        );
+        map.insert("empty".to_string(), vec![]);
+        let mut rng = thread_rng();
+
+        let res = template_evaluate("By &god!", &map, &mut rng);
+        assert!(res == "By Odin!" || res == "By Zeus!");
+        assert_eq!(
+            template_evaluate("By &empty!", &map, &mut rng),
+            "By &empty!"
+        );
+        assert_eq!(template_evaluate("By &gods!", &map, &mut rng), "By &gods!");
+    }
+
     #[test]
     fn test_template_evaluate() {
         let mut map = HashMap::new();
         map.insert("name".to_string(), "Alice".to_string());
         map.insert("lang".to_string(), "Rust".to_string());
+        let mut rng = thread_rng();
 
         assert_eq!(
-            template_evaluate("Hello, &name!", &map),
+            template_evaluate("Hello, &name!", &map, &mut rng),
             Cow::Borrowed("Hello, Alice!")
         );
         assert_eq!(
-            template_evaluate("Hello, &name! Welcome to &lang programming.", &map),
+            template_evaluate(
+                "Hello, &name! Welcome to &lang programming.",
+                &map,
+                &mut rng
+            ),
             Cow::Borrowed("Hello, Alice! Welcome to Rust programming.")
         );
         assert_eq!(
-            template_evaluate("No templates here.", &map),
+            template_evaluate("No templates here.", &map, &mut rng),
             Cow::Borrowed("No templates here.")
         );
     }
+
+    fn vocabulary(entries: &[(&str, &str)]) -> IndexMap<String, Vec<String>> {
+        let mut map: IndexMap<String, Vec<String>> = IndexMap::new();
+        for (name, value) in entries {
+            map.entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+        map
+    }
+
+    #[test]
+    fn test_template_expand_nested() {
+        let map = vocabulary(&[
+            ("oath", "by &god's &bodypart"),
+            ("god", "Odin"),
+            ("bodypart", "&bodyadj beard"),
+            ("bodyadj", "mighty"),
+        ]);
+        let mut rng = thread_rng();
+
+        assert_eq!(
+            template_expand("&oath!", &map, 3, &mut rng).unwrap(),
+            "by Odin's mighty beard!"
+        );
+        assert_eq!(
+            template_expand("&oath!", &map, 2, &mut rng),
+            Err(TemplateError::TooDeep {
+                name: "bodyadj".to_string(),
+                limit: 2
+            })
+        );
+        assert_eq!(check_templates(&map, 3), Ok(()));
+        assert!(check_templates(&map, 2).is_err());
+    }
+
+    #[test]
+    fn test_template_cycles_are_reported() {
+        let map = vocabulary(&[("a", "x &b"), ("b", "&c"), ("c", "&a"), ("ok", "fine")]);
+        let mut rng = thread_rng();
+
+        let err = template_expand("&a", &map, 100, &mut rng).unwrap_err();
+        assert_eq!(
+            err,
+            TemplateError::Cycle(vec!["a".into(), "b".into(), "c".into(), "a".into()])
+        );
+        assert_eq!(
+            err.to_string(),
+            "template \"a\" contains itself: &a -> &b -> &c -> &a"
+        );
+        assert_eq!(check_templates(&map, 100), Err(err));
+        assert_eq!(template_evaluate("&a &ok", &map, &mut rng), "&a &ok");
+        assert_eq!(
+            template_evaluate("&ok &missing", &map, &mut rng),
+            "fine &missing"
+        );
+    }
+
+    #[test]
+    fn test_template_braces_and_modifiers() {
+        let map = vocabulary(&[
+            ("god", "Odin"),
+            ("bodypart", "third leg"),
+            ("oath", "&{bodypart|plural} of &god"),
+        ]);
+        let mut rng = thread_rng();
+        let mut expand =
+            |input: &str| template_expand(input, &map, 8, &mut rng).map(Cow::into_owned);
+
+        assert_eq!(expand("&{god}ly").unwrap(), "Odinly");
+        assert_eq!(expand("R&&D && &god").unwrap(), "R&D & Odin");
+        assert_eq!(expand("&&god").unwrap(), "&god");
+        assert_eq!(expand("&{god|upper}!").unwrap(), "ODIN!");
+        assert_eq!(expand("&{bodypart|cap}").unwrap(), "Third leg");
+        assert_eq!(expand("&{oath|cap}").unwrap(), "Third legs of Odin");
+        assert_eq!(expand("&{bodypart|plural|upper}").unwrap(), "THIRD LEGS");
+        assert_eq!(expand("&{nope|cap} & co").unwrap(), "&{nope|cap} & co");
+
+        assert_eq!(
+            expand("By &{god, ").unwrap_err().to_string(),
+            "unterminated \"&{\" in \"&{god, \""
+        );
+        assert!(matches!(
+            expand("&{god|loud}"),
+            Err(TemplateError::Syntax { message, .. }) if message == "unknown modifier \"loud\""
+        ));
+        assert!(expand("&{}").is_err());
+        let known = |name: &str| map.contains_key(name);
+        assert!(check_placeholders("fine & dandy &{god|cap}", known).is_ok());
+        assert!(check_placeholders("&{god", known).is_err());
+        assert_eq!(
+            check_placeholders("hello &{gods|upper}.", known)
+                .unwrap_err()
+                .to_string(),
+            "unknown template \"gods\" in \"hello &{gods|upper}.\""
+        );
+        let missing = vocabulary(&[("oath", "by &god")]);
+        assert!(matches!(
+            check_templates(&missing, 8),
+            Err(TemplateError::Unknown { name, .. }) if name == "god"
+        ));
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(pluralize("Beard"), "Beards");
+        assert_eq!(pluralize("Liver and Lights"), "Liver and Lights");
+        assert_eq!(pluralize("Toenail"), "Toenails");
+        assert_eq!(pluralize("glass"), "glasses");
+        assert_eq!(pluralize("Paunch"), "Paunches");
+        assert_eq!(pluralize("belly"), "bellies");
+        assert_eq!(pluralize("Day"), "Days");
+        assert_eq!(pluralize("EYE"), "EYES");
+    }
 }
@@ -1,20 +1,33 @@
-import React, { useCallback, useReducer } from "react";
+import React, { useCallback, useEffect, useReducer, useRef } from "react";
 import { Wand2 } from "lucide-react";
 import { useDebounceCallback } from "usehooks-ts";
 
-import { AutoRP } from "yeold";
+import { AutoRP, Segment } from "yeold";
 
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { Input } from "@/components/ui/input";
 import { Button } from "@/components/ui/button";
 import { Checkbox } from "@/components/ui/checkbox";
 import { Label } from "@/components/ui/label";
+import { cn } from "@/lib/utils";
 
 const arp = AutoRP.default();
 
+/** A piece of the output, as shown to the user. */
+interface SegmentView {
+  original: string;
+  translated: string;
+  changed: boolean;
+  ruleId?: number;
+  /** Why the segment was changed, if it was */
+  note?: string;
+  rerollable: boolean;
+}
+
 interface ArpState {
   input: string;
   output: string;
+  segments: SegmentView[];
   prepend: boolean;
   append: boolean;
 }
@@ -22,53 +35,40 @@ interface ArpState {
 const DEFAULT_ARP_STATE: ArpState = Object.freeze({
   input: "",
   output: "",
+  segments: [],
   prepend: true,
   append: true,
 });
 
-type ArpAction =
-  | { type: "INPUT_TRANSLATE"; payload: string }
-  | { type: "UPDATE"; payload: Partial<ArpState> }
-  | { type: "TRANSLATE" };
+/** Copies what is shown of `handles` out of wasm. */
+function withSegments(
+  handles: Segment[]
+): Pick<ArpState, "output" | "segments"> {
+  const segments = handles.map((segment) => ({
+    original: segment.original,
+    translated: segment.translated,
+    changed: segment.changed,
+    ruleId: segment.ruleId,
+    note: segment.describe(),
+    rerollable: segment.can_reroll(),
+  }));
+  const output = segments.map((segment) => segment.translated).join("");
+  return { output, segments };
+}
+
+type ArpAction = { type: "UPDATE"; payload: Partial<ArpState> };
 
 function arpReducer(
   state: ArpState = DEFAULT_ARP_STATE,
   action: ArpAction
 ): ArpState {
   switch (action.type) {
-    case "INPUT_TRANSLATE": {
-      if (action.payload === state.input) {
-        return state;
-      }
-
-      return {
-        ...state,
-        input: action.payload,
-        output: arp.translate_postprocess(
-          action.payload,
-          state.prepend,
-          state.append
-        ),
-      };
-    }
     case "UPDATE": {
       return {
         ...state,
         ...action.payload,
       };
     }
-    case "TRANSLATE": {
-      const output = state.input
-        ? arp.translate_postprocess(state.input, state.prepend, state.append)
-        : "";
-      if (output == state.output) {
-        return state;
-      }
-      return {
-        ...state,
-        output,
-      };
-    }
     default:
       return state;
   }
@@ -76,25 +76,53 @@ function arpReducer(
 
 const YeoldTransformer = () => {
   const [state, dispatch] = useReducer(arpReducer, DEFAULT_ARP_STATE);
-
-  const translateDispatch = useDebounceCallback(
-    useCallback(() => dispatch({ type: "TRANSLATE" }), []),
-    200
+  // The segments as returned by wasm, handed back to it to re-roll one.
+  // They live in wasm memory, so they are kept out of the state and freed
+  // as soon as they are replaced.
+  const handles = useRef<Segment[]>([]);
+
+  const show = useCallback((next: Segment[]) => {
+    handles.current = next;
+    dispatch({ type: "UPDATE", payload: withSegments(next) });
+  }, []);
+
+  const free = useCallback(() => {
+    handles.current.forEach((segment) => segment.free());
+    handles.current = [];
+  }, []);
+
+  useEffect(() => free, [free]);
+
+  /** Translates `input`, split into the parts left alone and those changed. */
+  const translate = useCallback(
+    (input: string, prepend: boolean, append: boolean) => {
+      free();
+      show(input ? arp.translate_segments(input, prepend, append) : []);
+    },
+    [free, show]
   );
 
+  const translateDispatch = useDebounceCallback(translate, 200);
+
   const onChange = (e: React.ChangeEvent<HTMLInputElement>) => {
     dispatch({ type: "UPDATE", payload: { input: e.target.value } });
-    translateDispatch();
+    translateDispatch(e.target.value, state.prepend, state.append);
   };
 
   const onPrependChange = (checked: boolean) => {
     dispatch({ type: "UPDATE", payload: { prepend: checked } });
-    translateDispatch();
+    translateDispatch(state.input, checked, state.append);
   };
 
   const onAppendChange = (checked: boolean) => {
     dispatch({ type: "UPDATE", payload: { append: checked } });
-    translateDispatch();
+    translateDispatch(state.input, state.prepend, checked);
+  };
+
+  const onReroll = (index: number) => {
+    // wasm takes the segments over, freeing them, and returns them with one
+    // re-rolled.
+    show(arp.reroll_segment(handles.current, index));
   };
 
   const onSubmit = (e: React.FormEvent<HTMLFormElement>) => {
@@ -102,7 +130,7 @@ const YeoldTransformer = () => {
     if (translateDispatch.isPending()) {
       translateDispatch.cancel();
     }
-    dispatch({ type: "TRANSLATE" });
+    translate(state.input, state.prepend, state.append);
   };
 
   return (
@@ -158,7 +186,36 @@ const YeoldTransformer = () => {
             <div className="space-y-2">
               <label className="text-sm font-medium">Transformed Text</label>
               <div className="p-4 rounded-lg bg-slate-50 dark:bg-slate-900">
-                <p className="font-mono">{state.output}</p>
+                <p className="font-mono">
+                  {state.segments.map((segment, i) =>
+                    segment.rerollable ? (
+                      <button
+                        key={i}
+                        type="button"
+                        title={`${segment.note}\nClick to re-roll`}
+                        onClick={() => onReroll(i)}
+                        className={cn(
+                          "whitespace-pre-wrap rounded underline decoration-dotted decoration-slate-400 hover:bg-purple-100 dark:hover:bg-purple-900",
+                          segment.changed && "bg-amber-100 dark:bg-amber-900"
+                        )}
+                      >
+                        {segment.translated}
+                      </button>
+                    ) : (
+                      <span
+                        key={i}
+                        title={segment.note}
+                        className={cn(
+                          "whitespace-pre-wrap",
+                          segment.changed &&
+                            "rounded bg-amber-100 dark:bg-amber-900 cursor-help"
+                        )}
+                      >
+                        {segment.translated}
+                      </span>
+                    )
+                  )}
+                </p>
               </div>
             </div>
           )}